@@ -0,0 +1,11 @@
+-- History of power operations requested through the API
+CREATE TABLE operations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    machine TEXT NOT NULL,
+    action TEXT NOT NULL,
+    succeeded INTEGER NOT NULL,
+    error TEXT,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL
+);
+CREATE INDEX operations_machine_started_at ON operations (machine, started_at);
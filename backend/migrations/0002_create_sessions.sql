@@ -0,0 +1,10 @@
+-- Login sessions. `id` is the SHA-256 hash of the session token in the cookie,
+-- not to leak valid tokens by the database file.
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    claims TEXT NOT NULL,
+    id_token TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
@@ -0,0 +1,3 @@
+-- Refresh tokens to renew sessions before the ID tokens expire, until `max_expires_at`
+ALTER TABLE sessions ADD COLUMN refresh_token TEXT;
+ALTER TABLE sessions ADD COLUMN max_expires_at INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,18 @@
+-- Personal API tokens for automation. `token_hash` is the SHA-256 hash of the token,
+-- and `claims` are the ones of the owner on the last login at `claims_updated_at`.
+CREATE TABLE api_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash TEXT NOT NULL UNIQUE,
+    owner TEXT NOT NULL,
+    name TEXT NOT NULL,
+    claims TEXT NOT NULL,
+    claims_updated_at INTEGER NOT NULL,
+    read_only INTEGER NOT NULL,
+    -- JSON array of machine names the token is limited to, or NULL for all machines
+    machines TEXT,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    last_used_at INTEGER,
+    UNIQUE (owner, name)
+);
+CREATE INDEX api_tokens_expires_at ON api_tokens (expires_at);
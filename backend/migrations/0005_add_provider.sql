@@ -0,0 +1,29 @@
+-- OIDC providers which users logged in with. Existing rows are of the `[oidc]` provider.
+ALTER TABLE sessions ADD COLUMN provider TEXT NOT NULL DEFAULT 'default';
+
+-- `sub` is unique only within the provider, so tokens are owned by the pair of them.
+CREATE TABLE api_tokens_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash TEXT NOT NULL UNIQUE,
+    provider TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    name TEXT NOT NULL,
+    claims TEXT NOT NULL,
+    claims_updated_at INTEGER NOT NULL,
+    read_only INTEGER NOT NULL,
+    -- JSON array of machine names the token is limited to, or NULL for all machines
+    machines TEXT,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    last_used_at INTEGER,
+    UNIQUE (provider, owner, name)
+);
+INSERT INTO api_tokens_new
+    (id, token_hash, provider, owner, name, claims, claims_updated_at, read_only, machines,
+     created_at, expires_at, last_used_at)
+SELECT id, token_hash, 'default', owner, name, claims, claims_updated_at, read_only, machines,
+       created_at, expires_at, last_used_at
+FROM api_tokens;
+DROP TABLE api_tokens;
+ALTER TABLE api_tokens_new RENAME TO api_tokens;
+CREATE INDEX api_tokens_expires_at ON api_tokens (expires_at);
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::middlewares::AuthenticatedUser;
+use crate::policy::Action;
+use crate::sessions::{generate_token, hash_token};
+use crate::store::ApiTokenRecord;
+use crate::{AppState, Error};
+
+// Prefix to tell API tokens from ID tokens in Authorization header, and to find leaked ones
+const TOKEN_PREFIX: &str = "mlt_";
+// Upper bound of tokens per user, not to grow the database without limit
+const MAX_TOKENS_PER_USER: usize = 100;
+const MAX_NAME_LEN: usize = 64;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Settings of the API token requested by the user.
+#[derive(Debug, Clone)]
+pub struct NewApiToken {
+    pub name: String,
+    pub read_only: bool,
+    pub machines: Option<Vec<String>>,
+    pub expires_in_secs: u64,
+}
+
+/// Restrictions of the API token used for the request, applied in addition to the rules.
+#[derive(Debug, Clone)]
+pub struct TokenScope {
+    pub read_only: bool,
+    pub machines: Option<Vec<String>>,
+}
+impl TokenScope {
+    pub fn allows(&self, machine: &str, action: Action) -> bool {
+        (!self.read_only || action == Action::View)
+            && self
+                .machines
+                .as_ref()
+                .is_none_or(|machines| machines.iter().any(|m| m == machine))
+    }
+}
+impl From<&ApiTokenRecord> for TokenScope {
+    fn from(token: &ApiTokenRecord) -> Self {
+        TokenScope {
+            read_only: token.read_only,
+            machines: token.machines.clone(),
+        }
+    }
+}
+
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Create the API token of `user`, and return the token with its record.
+/// The token itself is returned only here, and only its hash is stored.
+pub async fn create_api_token(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    new: NewApiToken,
+) -> Result<(String, ApiTokenRecord), Error> {
+    let owner = owner_of(&user.claims)?;
+    let name = new.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(Error::BadRequest(
+            format!("name must be 1 to {} characters", MAX_NAME_LEN).into(),
+        ));
+    }
+    let max_lifetime_secs = state.reloadable().config.api_token.max_lifetime_secs;
+    if !(1..=max_lifetime_secs).contains(&new.expires_in_secs) {
+        return Err(Error::BadRequest(
+            format!("expires_in_secs must be 1 to {}", max_lifetime_secs).into(),
+        ));
+    }
+    if let Some(machines) = &new.machines {
+        if machines.is_empty() {
+            return Err(Error::BadRequest("machines must not be empty".into()));
+        }
+        let reloadable = state.reloadable();
+        if let Some(unknown) = machines
+            .iter()
+            .find(|m| !reloadable.drivers.contains_key(*m))
+        {
+            return Err(Error::BadRequest(
+                format!("machine {:?} is not found", unknown).into(),
+            ));
+        }
+    }
+
+    let token = format!("{}{}", TOKEN_PREFIX, generate_token());
+    let now = chrono::Utc::now().timestamp();
+    let mut record = ApiTokenRecord {
+        id: 0,
+        token_hash: hash_token(&token),
+        provider: user.provider.clone(),
+        owner,
+        name,
+        claims: user.claims.clone(),
+        claims_updated_at: now,
+        read_only: new.read_only,
+        machines: new.machines,
+        created_at: now,
+        expires_at: now.saturating_add(new.expires_in_secs.try_into().unwrap_or(i64::MAX)),
+        last_used_at: None,
+    };
+    let inserted = record.clone();
+    let id = match state
+        .store
+        .blocking(move |store| store.insert_api_token(&inserted, MAX_TOKENS_PER_USER))
+        .await
+    {
+        Err(Error::Database(rusqlite::Error::SqliteFailure(e, _)))
+            if e.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            return Err(Error::BadRequest(
+                format!("token {:?} already exists", record.name).into(),
+            ));
+        }
+        result => result?,
+    };
+    record.id = id.ok_or_else(|| {
+        Error::BadRequest(format!("at most {} tokens can be created", MAX_TOKENS_PER_USER).into())
+    })?;
+    Ok((token, with_claims_expiry(state, record)))
+}
+
+/// Find the API token in Authorization header if it has not expired, and the owner has logged
+/// in within `session.max_lifetime_secs`.
+// MEMO: tokens authorize by the claims of the last login, so they are refused after the time
+// sessions must log in again, not to keep roles revoked by the OIDC provider. They are accepted
+// again when the owner logs in, which updates the claims.
+pub async fn find_api_token(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<ApiTokenRecord>, Error> {
+    let token_hash = hash_token(token);
+    let now = chrono::Utc::now().timestamp();
+    let claims_updated_after = now.saturating_sub(claims_lifetime_secs(state));
+    state
+        .store
+        .blocking(move |store| store.use_api_token(&token_hash, now, claims_updated_after))
+        .await
+}
+
+fn claims_lifetime_secs(state: &AppState) -> i64 {
+    let max_lifetime_secs = state.reloadable().config.session.max_lifetime_secs;
+    max_lifetime_secs.try_into().unwrap_or(i64::MAX)
+}
+
+// Show when the token is refused unless the owner logs in again, if it is before the expiry.
+fn with_claims_expiry(state: &AppState, mut token: ApiTokenRecord) -> ApiTokenRecord {
+    let claims_expire_at = token
+        .claims_updated_at
+        .saturating_add(claims_lifetime_secs(state));
+    token.expires_at = token.expires_at.min(claims_expire_at);
+    token
+}
+
+/// List API tokens of `user`.
+pub async fn list_api_tokens(
+    state: &AppState,
+    user: &AuthenticatedUser,
+) -> Result<Vec<ApiTokenRecord>, Error> {
+    let owner = owner_of(&user.claims)?;
+    let provider = user.provider.clone();
+    let tokens = state
+        .store
+        .blocking(move |store| store.list_api_tokens(&provider, &owner))
+        .await?;
+    Ok(tokens
+        .into_iter()
+        .map(|token| with_claims_expiry(state, token))
+        .collect())
+}
+
+/// Revoke the API token of `id` of `user`.
+pub async fn delete_api_token(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    id: i64,
+) -> Result<(), Error> {
+    let owner = owner_of(&user.claims)?;
+    let provider = user.provider.clone();
+    let deleted = state
+        .store
+        .blocking(move |store| store.delete_api_token(id, &provider, &owner))
+        .await?;
+    if !deleted {
+        return Err(Error::NotFound("token is not found".into()));
+    }
+    Ok(())
+}
+
+/// Apply the latest claims of the user (e.g. on login) to their API tokens, so that tokens
+/// lose roles the user has lost.
+pub async fn update_claims(state: &AppState, provider: &str, claims: &serde_json::Value) {
+    let Ok(owner) = owner_of(claims) else {
+        return;
+    };
+    let provider = provider.to_string();
+    let claims = claims.clone();
+    let now = chrono::Utc::now().timestamp();
+    if let Err(e) = state
+        .store
+        .blocking(move |store| store.update_api_token_claims(&provider, &owner, &claims, now))
+        .await
+    {
+        tracing::warn!("failed to update claims of API tokens: {}", e);
+    }
+}
+
+fn owner_of(claims: &serde_json::Value) -> Result<String, Error> {
+    claims
+        .get("sub")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
+        .ok_or_else(|| Error::BadRequest("sub claim is required to own tokens".into()))
+}
+
+/// Delete expired API tokens periodically.
+pub fn spawn_cleanup(app_state: Arc<AppState>) {
+    let shutdown = app_state.shutdown.clone();
+    app_state.shutdown.clone().spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let now = chrono::Utc::now().timestamp();
+            match app_state
+                .store
+                .blocking(move |store| store.delete_expired_api_tokens(now))
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => tracing::debug!("{} expired API tokens are deleted", deleted),
+                Err(e) => tracing::warn!("failed to delete expired API tokens: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_without_restrictions_allows_all() {
+        let scope = TokenScope {
+            read_only: false,
+            machines: None,
+        };
+        assert!(scope.allows("server01", Action::View));
+        assert!(scope.allows("server01", Action::Start));
+        assert!(scope.allows("server01", Action::Stop));
+    }
+
+    #[test]
+    fn read_only_scope_allows_only_view() {
+        let scope = TokenScope {
+            read_only: true,
+            machines: None,
+        };
+        assert!(scope.allows("server01", Action::View));
+        assert!(!scope.allows("server01", Action::Start));
+        assert!(!scope.allows("server01", Action::Stop));
+    }
+
+    #[test]
+    fn scope_of_machines_allows_only_them() {
+        let scope = TokenScope {
+            read_only: false,
+            machines: Some(vec!["server01".to_string()]),
+        };
+        assert!(scope.allows("server01", Action::Start));
+        assert!(!scope.allows("server02", Action::View));
+        assert!(!scope.allows("server", Action::View));
+    }
+
+    #[test]
+    fn api_tokens_are_told_by_prefix() {
+        assert!(is_api_token(&format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            generate_token()
+        )));
+        assert!(!is_api_token("eyJhbGciOiJSUzI1NiJ9.e30.sig"));
+    }
+
+    #[test]
+    fn owner_is_sub_claim() {
+        assert_eq!(
+            owner_of(&serde_json::json!({"sub": "user1"})).unwrap(),
+            "user1"
+        );
+        assert!(owner_of(&serde_json::json!({"name": "user1"})).is_err());
+    }
+}
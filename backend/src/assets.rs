@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use axum::{routing::get_service, Router};
+use tower_http::services::{ServeDir, ServeFile};
+
+// Frontend directory used if the assets are not embedded and not overridden by config
+#[cfg(not(feature = "embed-frontend"))]
+const DEFAULT_FRONTEND_DIR: &str = "../frontend/";
+
+/// Routes serving the frontend: the trunk build output as fallback and static files on `/public`.
+/// If `frontend_dir` is set, files are served from the directory instead of embedded ones.
+/// Unknown paths are answered with `index.html`, so that reloading any page opens the frontend.
+pub fn routes<S>(frontend_dir: Option<&str>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    match frontend_dir {
+        Some(dir) => routes_from_dir(Path::new(dir)),
+        #[cfg(feature = "embed-frontend")]
+        None => embedded::routes(),
+        #[cfg(not(feature = "embed-frontend"))]
+        None => routes_from_dir(Path::new(DEFAULT_FRONTEND_DIR)),
+    }
+}
+
+fn routes_from_dir<S>(frontend_dir: &Path) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    tracing::info!("serving frontend from {}", frontend_dir.display());
+    Router::new()
+        .nest_service(
+            "/public",
+            get_service(
+                ServeDir::new(frontend_dir.join("public"))
+                    .precompressed_br()
+                    .precompressed_gzip(),
+            ),
+        )
+        .fallback_service(get_service(
+            ServeDir::new(frontend_dir.join("dist"))
+                .precompressed_br()
+                .precompressed_gzip()
+                .fallback(
+                    ServeFile::new(frontend_dir.join("dist").join("index.html"))
+                        .precompressed_br()
+                        .precompressed_gzip(),
+                ),
+        ))
+}
+
+#[cfg(feature = "embed-frontend")]
+mod embedded {
+    use axum::{
+        extract::Path,
+        http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        routing::get,
+        Router,
+    };
+    use base64::prelude::*;
+    use once_cell::sync::Lazy;
+    use regex::Regex;
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[folder = "../frontend/dist/"]
+    struct CompiledFiles;
+
+    #[derive(RustEmbed)]
+    #[folder = "../frontend/public/"]
+    struct StaticFiles;
+
+    // trunk appends the content hash to file names, e.g. `machine-launcher-frontend-0123456789abcdef.js`
+    static HASHED_FILE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"-[0-9a-f]{16,}[._]").unwrap());
+
+    pub(super) fn routes<S>() -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        tracing::info!("serving embedded frontend");
+        Router::new()
+            .route(
+                "/public/{*path}",
+                get(|Path(path): Path<String>, headers: HeaderMap| async move {
+                    serve::<StaticFiles>(&path, &headers)
+                }),
+            )
+            .fallback(|uri: Uri, headers: HeaderMap| async move {
+                let path = uri.path().trim_start_matches('/');
+                let path = if path.is_empty() || path.ends_with('/') {
+                    format!("{}index.html", path)
+                } else {
+                    path.to_string()
+                };
+                if CompiledFiles::get(&path).is_some() {
+                    serve::<CompiledFiles>(&path, &headers)
+                } else {
+                    serve::<CompiledFiles>("index.html", &headers)
+                }
+            })
+    }
+
+    fn serve<E: RustEmbed>(path: &str, headers: &HeaderMap) -> Response {
+        let Some(file) = E::get(path) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let content_type = file.metadata.mimetype().to_string();
+
+        // serve precompressed variants (e.g. `index.html.br`) if the client accepts them
+        let accept_encoding = headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let (file, content_encoding) = [("br", "br"), ("gzip", "gz")]
+            .into_iter()
+            .filter(|(encoding, _)| {
+                accept_encoding
+                    .split(',')
+                    .any(|e| e.split(';').next().unwrap_or_default().trim() == *encoding)
+            })
+            .find_map(|(encoding, ext)| {
+                E::get(&format!("{}.{}", path, ext)).map(|f| (f, Some(encoding)))
+            })
+            .unwrap_or((file, None));
+
+        let etag = format!(
+            "\"{}\"",
+            BASE64_URL_SAFE_NO_PAD.encode(file.metadata.sha256_hash())
+        );
+        let cache_control = if HASHED_FILE_RE.is_match(path) {
+            "public, max-age=31536000, immutable"
+        } else {
+            "no-cache"
+        };
+        let mut res_headers = HeaderMap::new();
+        res_headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+        res_headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+        res_headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+
+        let not_modified = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+        if not_modified {
+            return (StatusCode::NOT_MODIFIED, res_headers).into_response();
+        }
+
+        if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+            res_headers.insert(header::CONTENT_TYPE, content_type);
+        }
+        if let Some(encoding) = content_encoding {
+            res_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        (res_headers, file.data).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn unknown_paths_are_answered_with_index_html() {
+        let dir =
+            std::env::temp_dir().join(format!("machine-launcher-assets-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("dist")).unwrap();
+        std::fs::write(dir.join("dist/index.html"), "index").unwrap();
+        std::fs::write(dir.join("dist/app.js"), "app").unwrap();
+        let app: Router = routes(Some(dir.to_str().unwrap()));
+
+        let mut bodies = vec![];
+        for path in ["/", "/app.js", "/tokens", "/servers/foo"] {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK, "{}", path);
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            bodies.push(String::from_utf8(body.to_vec()).unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(bodies, ["index", "app", "index", "index"]);
+    }
+}
@@ -1,25 +1,536 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::ctl::{CtlCommand, OutputFormat};
 
 /// machine-launcher is the web server to manage the power of servers.
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
     /// Config file
-    #[arg(long)]
-    pub config: String,
+    #[arg(long, required = true)]
+    pub config: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Validate the config file without starting the server
+    CheckConfig {
+        /// Config file
+        #[arg(long)]
+        config: String,
+    },
+    /// Hash the password read from stdin for `local_auth.users`
+    HashPassword,
+    /// Manage machines directly without the web server
+    Ctl {
+        /// Config file
+        #[arg(long)]
+        config: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
 }
 
+use std::{
+    collections::HashMap,
+    env, fmt,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Config {
     pub url: String,
-    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub api_token: ApiTokenConfig,
+    // OIDC provider, named "default" unless `name` is set
+    pub oidc: Option<OidcConfig>,
+    // OIDC providers users choose from on login, in addition to `oidc`
+    #[serde(default)]
+    pub oidc_providers: Vec<OidcConfig>,
+    // Users authenticated by this server itself, without OIDC providers
+    pub local_auth: Option<LocalAuthConfig>,
+    // Policy used for drivers without their own `allow_*` settings
+    #[serde(default)]
+    pub default_policy: PolicyConfig,
     pub drivers: Vec<DriverType>,
 }
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BoxError> {
+        Self::load_with(path, true)
+    }
+
+    /// Load the config without checking files only the server uses (TLS certificates,
+    /// the frontend and the database), for `ctl` which may run without them.
+    pub fn load_without_server_files<P: AsRef<Path>>(path: P) -> Result<Self, BoxError> {
+        Self::load_with(path, false)
+    }
+
+    fn load_with<P: AsRef<Path>>(path: P, server_files: bool) -> Result<Self, BoxError> {
+        let config_str = std::fs::read_to_string(path)?;
+        let config = Self::parse(&config_str)?;
+        let problems = config.validate_with(server_files);
+        if !problems.is_empty() {
+            return Err(ConfigProblems(problems).into());
+        }
+        Ok(config)
+    }
+
+    pub fn parse(config_str: &str) -> Result<Self, ConfigProblems> {
+        let config_str_templated = substitute_env_variables(config_str).map_err(ConfigProblems)?;
+        let mut config: Config = toml::from_str(&config_str_templated).map_err(|e| {
+            ConfigProblems(vec![ConfigProblem {
+                location: "toml".to_string(),
+                message: e.to_string(),
+            }])
+        })?;
+        config.read_secret_files().map_err(ConfigProblems)?;
+        Ok(config)
+    }
+
+    /// All OIDC providers, `oidc` first and then `oidc_providers`.
+    pub fn oidc_configs(&self) -> impl Iterator<Item = &OidcConfig> {
+        self.oidc.iter().chain(&self.oidc_providers)
+    }
+
+    // Locations of OIDC providers in the config file, in the order of `oidc_configs`.
+    fn oidc_locations(&self) -> Vec<String> {
+        self.oidc
+            .iter()
+            .map(|_| "oidc".to_string())
+            .chain(
+                self.oidc_providers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| format!("oidc_providers[{}] (name {:?})", i, c.name)),
+            )
+            .collect()
+    }
+
+    // Fill secrets from `*_file` settings (e.g. Docker/Kubernetes secrets mounted as files).
+    fn read_secret_files(&mut self) -> Result<(), Vec<ConfigProblem>> {
+        let mut problems = vec![];
+        let locations = self.oidc_locations();
+        let oidc_configs = self.oidc.iter_mut().chain(self.oidc_providers.iter_mut());
+        for (location, oidc) in locations.into_iter().zip(oidc_configs) {
+            if let Err(message) = read_secret_file(
+                "client_secret",
+                &mut oidc.client_secret,
+                &oidc.client_secret_file,
+            ) {
+                problems.push(ConfigProblem { location, message });
+            }
+        }
+        if let Some(local_auth) = &mut self.local_auth {
+            if let Err(message) = local_auth.read_users_file() {
+                problems.push(ConfigProblem {
+                    location: "local_auth.users_file".to_string(),
+                    message,
+                });
+            }
+        }
+        for (i, driver) in self.drivers.iter_mut().enumerate() {
+            if let DriverType::Ipmi(c) = driver {
+                let location = format!("drivers[{}] (name {:?})", i, c.name);
+                if let Err(message) =
+                    read_secret_file("password", &mut c.password, &c.password_file)
+                {
+                    problems.push(ConfigProblem { location, message });
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Validate values of the parsed config. Returns all problems found.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        self.validate_with(true)
+    }
+
+    // Validate values, and files only the server uses if `server_files` is true.
+    fn validate_with(&self, server_files: bool) -> Vec<ConfigProblem> {
+        let mut problems = vec![];
+
+        if let Err(e) = url::Url::parse(&self.url) {
+            problems.push(ConfigProblem {
+                location: "url".to_string(),
+                message: format!("invalid URL {:?}: {}", self.url, e),
+            });
+        }
+        if self.server.listen.is_empty() {
+            problems.push(ConfigProblem {
+                location: "server.listen".to_string(),
+                message: "at least one address must be set".to_string(),
+            });
+        }
+        if server_files {
+            if let Some(tls) = &self.server.tls {
+                for (key, file) in [("cert_file", &tls.cert_file), ("key_file", &tls.key_file)] {
+                    if let Err(e) = std::fs::metadata(file) {
+                        problems.push(ConfigProblem {
+                            location: format!("server.tls.{}", key),
+                            message: format!("{:?} cannot be read: {}", file, e),
+                        });
+                    }
+                }
+            }
+            if let Some(dir) = &self.server.frontend_dir {
+                if !Path::new(dir).is_dir() {
+                    problems.push(ConfigProblem {
+                        location: "server.frontend_dir".to_string(),
+                        message: format!("{:?} is not a directory", dir),
+                    });
+                }
+            }
+            if let Some(path) = &self.storage.path {
+                let dir = match Path::new(path).parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                if !dir.is_dir() {
+                    problems.push(ConfigProblem {
+                        location: "storage.path".to_string(),
+                        message: format!("directory of {:?} does not exist", path),
+                    });
+                }
+            }
+        }
+        if self.session.max_lifetime_secs == 0 {
+            problems.push(ConfigProblem {
+                location: "session.max_lifetime_secs".to_string(),
+                message: "must be greater than 0".to_string(),
+            });
+        }
+        if self.api_token.max_lifetime_secs == 0 {
+            problems.push(ConfigProblem {
+                location: "api_token.max_lifetime_secs".to_string(),
+                message: "must be greater than 0".to_string(),
+            });
+        }
+        if self.oidc.is_none() && self.oidc_providers.is_empty() && self.local_auth.is_none() {
+            problems.push(ConfigProblem {
+                location: "oidc".to_string(),
+                message: "oidc, oidc_providers or local_auth must be set".to_string(),
+            });
+        }
+        let mut provider_names = HashMap::<&str, String>::new();
+        for (location, oidc) in self.oidc_locations().into_iter().zip(self.oidc_configs()) {
+            // MEMO: name is used in URL paths like driver names.
+            if !hostname_validator::is_valid(&oidc.name) {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: "name must be hostname format (alphanumerics, '-' and '.')"
+                        .to_string(),
+                });
+            }
+            if oidc.name == LOCAL_PROVIDER_NAME {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: format!("name {:?} is reserved for local_auth", LOCAL_PROVIDER_NAME),
+                });
+            }
+            if let Some(first) = provider_names.get(oidc.name.as_str()) {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: format!("name is duplicated with {}", first),
+                });
+            } else {
+                provider_names.insert(&oidc.name, location.clone());
+            }
+            if let Err(e) = url::Url::parse(&oidc.provider_url) {
+                problems.push(ConfigProblem {
+                    location: format!("{}.provider_url", location),
+                    message: format!("invalid URL {:?}: {}", oidc.provider_url, e),
+                });
+            }
+            if let Err(e) = jmespath::compile(&oidc.role_attribute_path) {
+                problems.push(ConfigProblem {
+                    location: format!("{}.role_attribute_path", location),
+                    message: format!("invalid JMESPath expression: {}", e),
+                });
+            }
+        }
+        if let Some(local_auth) = &self.local_auth {
+            problems.extend(local_auth.validate());
+        }
+        for message in self.default_policy.validate() {
+            problems.push(ConfigProblem {
+                location: "default_policy".to_string(),
+                message,
+            });
+        }
+
+        let mut names = HashMap::<&str, usize>::new();
+        for (i, driver) in self.drivers.iter().enumerate() {
+            let location = format!("drivers[{}] (name {:?})", i, driver.name());
+            // MEMO: name is used in URL paths, so it is restricted to the hostname charset.
+            if !hostname_validator::is_valid(driver.name()) {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: "name must be hostname format (alphanumerics, '-' and '.')"
+                        .to_string(),
+                });
+            }
+            if let Some(first) = names.get(driver.name()) {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: format!("name is duplicated with drivers[{}]", first),
+                });
+            } else {
+                names.insert(driver.name(), i);
+            }
+            for message in driver.validate() {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message,
+                });
+            }
+        }
+
+        problems
+    }
+}
+
+// `${VAR}` or `${VAR:-default}`
+static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\{(\w+)(?::-([^}]*))?\}").unwrap());
+
+// Substitute environment variables. If some variables without default are not set,
+// all of them are returned as problems.
+fn substitute_env_variables(input: &str) -> Result<String, Vec<ConfigProblem>> {
+    let mut problems = vec![];
+    let mut res = String::with_capacity(input.len());
+    for (i, line) in input.split_inclusive('\n').enumerate() {
+        let substituted = RE.replace_all(line, |caps: &regex::Captures| {
+            let name = &caps[1];
+            match (env::var(name), caps.get(2)) {
+                // Same as shell, the default is also used when the variable is set but empty.
+                (Ok(val), Some(default)) if val.is_empty() => default.as_str().to_string(),
+                (Ok(val), _) => val,
+                (Err(_), Some(default)) => default.as_str().to_string(),
+                (Err(_), None) => {
+                    problems.push(ConfigProblem {
+                        location: format!("line {}", i + 1),
+                        message: format!("environment variable {} is not set", name),
+                    });
+                    String::new()
+                }
+            }
+        });
+        res.push_str(&substituted);
+    }
+    if problems.is_empty() {
+        Ok(res)
+    } else {
+        Err(problems)
+    }
+}
+
+// Read the secret from `file` into `value` if `file` is set. Either of them must be set.
+fn read_secret_file(key: &str, value: &mut String, file: &Option<String>) -> Result<(), String> {
+    match file {
+        Some(_) if !value.is_empty() => Err(format!(
+            "{} and {}_file cannot be set at the same time",
+            key, key
+        )),
+        Some(file) => {
+            let secret = std::fs::read_to_string(file)
+                .map_err(|e| format!("{}_file {:?} cannot be read: {}", key, file, e))?;
+            *value = secret.trim_end_matches(['\r', '\n']).to_string();
+            if value.is_empty() {
+                return Err(format!("{}_file {:?} is empty", key, file));
+            }
+            Ok(())
+        }
+        None if value.is_empty() => Err(format!("{} or {}_file must be set", key, key)),
+        None => Ok(()),
+    }
+}
+
+static MAC_ADDR_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([0-9A-Fa-f]{2}:){5}[0-9A-Fa-f]{2}$").unwrap());
+
+/// A problem found in the config file with where it is.
+#[derive(Debug)]
+pub struct ConfigProblem {
+    pub location: String,
+    pub message: String,
+}
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Problems which make the config file unable to be loaded.
+#[derive(Debug)]
+pub struct ConfigProblems(pub Vec<ConfigProblem>);
+impl fmt::Display for ConfigProblems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config:")?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for ConfigProblems {}
+
+/// Validate the config file without side effects such as network access.
+/// Returns all problems found.
+pub fn check_config<P: AsRef<Path>>(path: P) -> Vec<ConfigProblem> {
+    let path = path.as_ref();
+    let config_str = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            return vec![ConfigProblem {
+                location: path.display().to_string(),
+                message: format!("cannot be read: {}", e),
+            }]
+        }
+    };
+
+    match Config::parse(&config_str) {
+        Ok(config) => config.validate(),
+        Err(problems) => problems.0,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerConfig {
+    // Addresses to listen on, this format must be ADDRESS:PORT (e.g. "0.0.0.0:8080", "[::]:8080").
+    // They are ignored if sockets are passed by systemd socket activation (listenfd).
+    #[serde(default = "default_listen")]
+    pub listen: Vec<SocketAddr>,
+
+    // Serve HTTPS instead of HTTP if set
+    pub tls: Option<TlsConfig>,
+
+    // Directory containing the frontend `dist/` and `public/` to serve them from disk
+    // (e.g. for frontend development). If not set, embedded files are served if the binary is
+    // built with `embed-frontend` feature, otherwise "../frontend/" is used.
+    pub frontend_dir: Option<String>,
 
-#[derive(Debug, Deserialize)]
+    // Seconds to wait for running requests and power operations on SIGTERM/SIGINT
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+
+    // Addresses of reverse proxies in front of this server. Client addresses used to rate-limit
+    // logins are taken from `X-Forwarded-For` or `Forwarded` only if requests come from them.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: default_listen(),
+            tls: None,
+            frontend_dir: None,
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+fn default_listen() -> Vec<SocketAddr> {
+    vec![SocketAddr::from(([0, 0, 0, 0], 8080))]
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TlsConfig {
+    // Path to the PEM file of the certificate chain. It is reloaded when the file is modified.
+    pub cert_file: String,
+
+    // Path to the PEM file of the private key. It is reloaded when the file is modified.
+    pub key_file: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct StorageConfig {
+    // Path to the SQLite database file, created if not exists.
+    // If not set, data is kept in memory and lost on exit.
+    // The file contains ID tokens and refresh tokens of login sessions in plaintext, which
+    // let anyone reading it get new tokens from the OIDC providers. It is created readable
+    // only by the owner; keep it and its backups as private as the client secrets.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SessionConfig {
+    // Maximum seconds of a login session. Sessions are renewed by refresh tokens until this
+    // if the OIDC provider issues them, otherwise they end when the ID token expires.
+    #[serde(default = "default_session_max_lifetime_secs")]
+    pub max_lifetime_secs: u64,
+}
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            max_lifetime_secs: default_session_max_lifetime_secs(),
+        }
+    }
+}
+
+fn default_session_max_lifetime_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ApiTokenConfig {
+    // Maximum seconds until personal API tokens expire. Users choose the expiry within this.
+    // Tokens authorize by the claims of the owner on the last login, so they are also refused
+    // when the owner has not logged in within session.max_lifetime_secs, until the owner logs in
+    // again. Roles revoked by the OIDC provider are not kept longer than sessions by tokens.
+    #[serde(default = "default_api_token_max_lifetime_secs")]
+    pub max_lifetime_secs: u64,
+}
+impl Default for ApiTokenConfig {
+    fn default() -> Self {
+        ApiTokenConfig {
+            max_lifetime_secs: default_api_token_max_lifetime_secs(),
+        }
+    }
+}
+
+fn default_api_token_max_lifetime_secs() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct OidcConfig {
+    // Name is identifier used in `/auth/login/{name}`. It must be unique.
+    #[serde(default = "default_oidc_name")]
+    pub name: String,
+
+    // Name displayed on the login page instead of `name`
+    pub display_name: Option<String>,
+
     // The URL of OpenID Provider. (https://openid.net/specs/openid-connect-core-1_0.html#Terminology)
     pub provider_url: String,
 
@@ -27,27 +538,379 @@ pub struct OidcConfig {
     pub client_id: String,
 
     // OIDC Client Secret. (https://openid.net/specs/openid-connect-core-1_0.html#Terminology)
+    #[serde(default)]
     pub client_secret: String,
 
+    // Path to the file containing OIDC Client Secret. Either this or client_secret must be set.
+    pub client_secret_file: Option<String>,
+
     // role_attribute_path is in JMESPath format. Only entities that return true are allowed.
+    // It is evaluated against the claims and the request context (see `policy::Authorizer`).
+    // The context keys `claims`, `request`, `now` and `machine` override claims of the same
+    // names, which are still available as e.g. `claims.request`.
+    pub role_attribute_path: String,
+}
+
+fn default_oidc_name() -> String {
+    "default".to_string()
+}
+
+/// Name of the provider of local accounts, used for sessions and API tokens like OIDC providers.
+pub const LOCAL_PROVIDER_NAME: &str = "local";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LocalAuthConfig {
+    // Accept local accounts only while no OIDC provider is available (e.g. the internet link is
+    // down on startup). If false, they are accepted alongside OIDC providers.
+    #[serde(default)]
+    pub fallback_only: bool,
+
+    // Name displayed on the login page
+    #[serde(default = "default_local_auth_display_name")]
+    pub display_name: String,
+
+    // Seconds until sessions of local accounts expire. They are limited by session.max_lifetime_secs.
+    #[serde(default = "default_local_auth_session_lifetime_secs")]
+    pub session_lifetime_secs: u64,
+
+    // role_attribute_path is in JMESPath format like the one of OIDC providers, evaluated with
+    // the same context keys.
+    // Claims of local accounts are `iss` ("local"), `sub` and `name` (the username) and `roles`.
+    #[serde(default = "default_local_auth_role_attribute_path")]
     pub role_attribute_path: String,
+
+    #[serde(default)]
+    pub users: Vec<LocalUserConfig>,
+
+    // Path to the TOML file containing `[[users]]` in the same format as `users`.
+    // They are added to `users`, and re-read when the config file is reloaded.
+    pub users_file: Option<String>,
+}
+impl LocalAuthConfig {
+    fn read_users_file(&mut self) -> Result<(), String> {
+        #[derive(Deserialize)]
+        struct UsersFile {
+            #[serde(default)]
+            users: Vec<LocalUserConfig>,
+        }
+        let Some(file) = &self.users_file else {
+            return Ok(());
+        };
+        let content = std::fs::read_to_string(file)
+            .map_err(|e| format!("{:?} cannot be read: {}", file, e))?;
+        let users_file: UsersFile =
+            toml::from_str(&content).map_err(|e| format!("{:?} is invalid: {}", file, e))?;
+        self.users.extend(users_file.users);
+        Ok(())
+    }
+
+    fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = vec![];
+        if self.users.is_empty() {
+            problems.push(ConfigProblem {
+                location: "local_auth".to_string(),
+                message: "users or users_file must contain at least one user".to_string(),
+            });
+        }
+        if self.session_lifetime_secs == 0 {
+            problems.push(ConfigProblem {
+                location: "local_auth.session_lifetime_secs".to_string(),
+                message: "must be greater than 0".to_string(),
+            });
+        }
+        if let Err(e) = jmespath::compile(&self.role_attribute_path) {
+            problems.push(ConfigProblem {
+                location: "local_auth.role_attribute_path".to_string(),
+                message: format!("invalid JMESPath expression: {}", e),
+            });
+        }
+        let mut usernames = HashMap::<&str, usize>::new();
+        for (i, user) in self.users.iter().enumerate() {
+            let location = format!("local_auth.users[{}] (username {:?})", i, user.username);
+            if user.username.is_empty() {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: "username must not be empty".to_string(),
+                });
+            }
+            if let Some(first) = usernames.get(user.username.as_str()) {
+                problems.push(ConfigProblem {
+                    location: location.clone(),
+                    message: format!("username is duplicated with local_auth.users[{}]", first),
+                });
+            } else {
+                usernames.insert(&user.username, i);
+            }
+            if let Err(e) = argon2::PasswordHash::new(&user.password_hash) {
+                problems.push(ConfigProblem {
+                    location,
+                    message: format!("password_hash should be argon2 PHC string format: {}", e),
+                });
+            }
+        }
+        problems
+    }
+}
+
+fn default_local_auth_display_name() -> String {
+    "Local account".to_string()
+}
+
+fn default_local_auth_session_lifetime_secs() -> u64 {
+    12 * 60 * 60
+}
+
+fn default_local_auth_role_attribute_path() -> String {
+    "contains(roles, 'admin')".to_string()
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LocalUserConfig {
+    pub username: String,
+
+    // Password hashed by argon2 in PHC string format (`$argon2id$v=19$...`).
+    // It can be generated by `machine-launcher hash-password`.
+    pub password_hash: String,
+
+    // Roles set to the `roles` claim
+    #[serde(default)]
+    pub roles: Vec<String>,
+
+    // Refuse logins of the user. Existing sessions and API tokens of the user are also refused,
+    // as well as the ones of users removed from the config.
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(tag = "type")]
 pub enum DriverType {
     Debug(DriverDebug),
     Ipmi(DriverIpmi),
     Wol(DriverWol),
 }
+impl DriverType {
+    pub fn name(&self) -> &str {
+        match self {
+            DriverType::Debug(c) => &c.name,
+            DriverType::Ipmi(c) => &c.name,
+            DriverType::Wol(c) => &c.name,
+        }
+    }
+
+    pub fn metadata(&self) -> &MachineMetadata {
+        match self {
+            DriverType::Debug(c) => &c.metadata,
+            DriverType::Ipmi(c) => &c.metadata,
+            DriverType::Wol(c) => &c.metadata,
+        }
+    }
+
+    pub fn policy(&self) -> &PolicyConfig {
+        match self {
+            DriverType::Debug(c) => &c.policy,
+            DriverType::Ipmi(c) => &c.policy,
+            DriverType::Wol(c) => &c.policy,
+        }
+    }
+
+    // Return the config except the policy, to compare settings which affect the driver itself.
+    pub fn without_policy(&self) -> DriverType {
+        let mut driver = self.clone();
+        match &mut driver {
+            DriverType::Debug(c) => c.policy = PolicyConfig::default(),
+            DriverType::Ipmi(c) => c.policy = PolicyConfig::default(),
+            DriverType::Wol(c) => c.policy = PolicyConfig::default(),
+        }
+        driver
+    }
 
-#[derive(Debug, Deserialize)]
+    // Validate driver specific values without network access.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = self.policy().validate();
+        match self {
+            DriverType::Debug(c) => {
+                if !(0.0..=1.0).contains(&c.behavior.failure_rate) {
+                    problems.push(format!(
+                        "failure_rate must be between 0.0 and 1.0, but {}",
+                        c.behavior.failure_rate
+                    ));
+                }
+            }
+            DriverType::Ipmi(c) => {
+                let valid = c
+                    .server_addr
+                    .rsplit_once(':')
+                    .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+                if !valid {
+                    problems.push(format!(
+                        "server_addr must be ADDRESS:PORT format, but {:?}",
+                        c.server_addr
+                    ));
+                }
+            }
+            DriverType::Wol(c) => {
+                if !MAC_ADDR_RE.is_match(&c.mac_addr) {
+                    problems.push(format!(
+                        "mac_addr must be XX:XX:XX:XX:XX:XX format, but {:?}",
+                        c.mac_addr
+                    ));
+                }
+                if c.ip_addr.parse::<IpAddr>().is_err() {
+                    problems.push(format!("ip_addr is not IP address: {:?}", c.ip_addr));
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PolicyConfig {
+    // Rules in JMESPath format evaluated against the same document as role_attribute_path.
+    // Only entities that return true are allowed to see the machine, start it and stop it.
+    // If not set, the default policy is used, and the action is allowed if it is not set either.
+    pub allow_view: Option<String>,
+    pub allow_start: Option<String>,
+    pub allow_stop: Option<String>,
+}
+impl PolicyConfig {
+    fn validate(&self) -> Vec<String> {
+        [
+            ("allow_view", &self.allow_view),
+            ("allow_start", &self.allow_start),
+            ("allow_stop", &self.allow_stop),
+        ]
+        .into_iter()
+        .filter_map(|(key, expr)| {
+            let e = jmespath::compile(expr.as_deref()?).err()?;
+            Some(format!("{} is invalid JMESPath expression: {}", key, e))
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct MachineMetadata {
+    // Name displayed on UI instead of `name`
+    pub display_name: Option<String>,
+
+    // Hostname displayed on UI. If not set, each driver decides it from its own settings.
+    pub hostname: Option<String>,
+
+    // Free-form description displayed on UI
+    pub description: Option<String>,
+
+    // Tags to classify the machine
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    // URL or path of the icon image. If not set, the default icon is used.
+    pub icon: Option<String>,
+
+    // Links related to the machine (e.g. web UI, SSH)
+    #[serde(default)]
+    pub links: Vec<MachineLink>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MachineLink {
+    // Label of the link
+    pub name: String,
+
+    // URL of the link (e.g. https://nas.example.com, ssh://user@nas.example.com)
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct DriverDebug {
     // Name is identifier. It must be unique.
     pub name: String,
+
+    // Behavior to simulate a real machine
+    #[serde(flatten)]
+    pub behavior: DebugBehavior,
+
+    // Metadata displayed on UI
+    #[serde(flatten)]
+    pub metadata: MachineMetadata,
+
+    // Rules of users allowed to operate the machine
+    #[serde(flatten)]
+    pub policy: PolicyConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct DebugBehavior {
+    // Power state just after the process starts
+    #[serde(default)]
+    pub initial_state: DebugPowerState,
+
+    // Seconds to take from start to running. While booting, the machine is reported as not running.
+    #[serde(default)]
+    pub boot_duration_secs: u64,
+
+    // Seconds to take from stop to stopped. While shutting down, the machine is reported as running.
+    #[serde(default)]
+    pub shutdown_duration_secs: u64,
+
+    // Milliseconds to wait on every status call. Other tasks of the async runtime are not blocked.
+    #[serde(default)]
+    pub status_latency_ms: u64,
+
+    // Probability (0.0 to 1.0) that each operation fails
+    #[serde(default)]
+    pub failure_rate: f64,
+
+    // Operations that always fail
+    #[serde(default)]
+    pub always_fail: Vec<DebugOperation>,
+
+    // Failures scripted per call, in the order of calls to the driver since it is created.
+    // The n-th call fails if the n-th entry is its operation or "any", e.g. ["none", "status"]
+    // fails the second call if it is status. Calls after them follow always_fail and failure_rate.
+    #[serde(default)]
+    pub fail_on: Vec<DebugScriptedFailure>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugPowerState {
+    Running,
+    #[default]
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugOperation {
+    Start,
+    Status,
+    Stop,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugScriptedFailure {
+    None,
+    Any,
+    Start,
+    Status,
+    Stop,
+}
+impl DebugScriptedFailure {
+    pub fn matches(&self, operation: DebugOperation) -> bool {
+        match self {
+            DebugScriptedFailure::None => false,
+            DebugScriptedFailure::Any => true,
+            DebugScriptedFailure::Start => operation == DebugOperation::Start,
+            DebugScriptedFailure::Status => operation == DebugOperation::Status,
+            DebugScriptedFailure::Stop => operation == DebugOperation::Stop,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct DriverIpmi {
     // Name is identifier. It must be unique.
     pub name: String,
@@ -59,10 +922,22 @@ pub struct DriverIpmi {
     pub username: String,
 
     // IPMI password
+    #[serde(default)]
     pub password: String,
+
+    // Path to the file containing IPMI password. Either this or password must be set.
+    pub password_file: Option<String>,
+
+    // Metadata displayed on UI
+    #[serde(flatten)]
+    pub metadata: MachineMetadata,
+
+    // Rules of users allowed to operate the machine
+    #[serde(flatten)]
+    pub policy: PolicyConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct DriverWol {
     // Name is identifier. It must be unique.
     pub name: String,
@@ -72,4 +947,223 @@ pub struct DriverWol {
 
     // IP Address to check the server status for Wake-on-LAN
     pub ip_addr: String,
+
+    // Metadata displayed on UI
+    #[serde(flatten)]
+    pub metadata: MachineMetadata,
+
+    // Rules of users allowed to operate the machine
+    #[serde(flatten)]
+    pub policy: PolicyConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_env_variables_replaces_set_variables() {
+        env::set_var("ML_TEST_SUBST_SET", "value");
+        assert_eq!(
+            substitute_env_variables(
+                "a = \"${ML_TEST_SUBST_SET}\"\nb = \"${ML_TEST_SUBST_SET:-x}\"\n"
+            )
+            .unwrap(),
+            "a = \"value\"\nb = \"value\"\n"
+        );
+    }
+
+    #[test]
+    fn substitute_env_variables_uses_default_when_unset_or_empty() {
+        env::remove_var("ML_TEST_SUBST_UNSET");
+        env::set_var("ML_TEST_SUBST_EMPTY", "");
+        assert_eq!(
+            substitute_env_variables("${ML_TEST_SUBST_UNSET:-d1} ${ML_TEST_SUBST_EMPTY:-d2}")
+                .unwrap(),
+            "d1 d2"
+        );
+        assert_eq!(
+            substitute_env_variables("${ML_TEST_SUBST_UNSET:-}").unwrap(),
+            ""
+        );
+        assert_eq!(
+            substitute_env_variables("${ML_TEST_SUBST_EMPTY}").unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn substitute_env_variables_reports_all_unset_variables() {
+        env::remove_var("ML_TEST_SUBST_MISSING1");
+        env::remove_var("ML_TEST_SUBST_MISSING2");
+        let problems = substitute_env_variables(
+            "a = \"${ML_TEST_SUBST_MISSING1}\"\nb = \"${ML_TEST_SUBST_MISSING2}\"\n",
+        )
+        .unwrap_err();
+        let problems: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "line 1: environment variable ML_TEST_SUBST_MISSING1 is not set",
+                "line 2: environment variable ML_TEST_SUBST_MISSING2 is not set",
+            ]
+        );
+    }
+
+    #[test]
+    fn substitute_env_variables_keeps_other_text() {
+        assert_eq!(
+            substitute_env_variables("$HOME ${} ${not-a-name}").unwrap(),
+            "$HOME ${} ${not-a-name}"
+        );
+    }
+
+    #[test]
+    fn read_secret_file_rejects_empty_value_without_file() {
+        let mut value = String::new();
+        assert!(read_secret_file("password", &mut value, &None).is_err());
+        let mut value = "secret".to_string();
+        assert!(read_secret_file("password", &mut value, &None).is_ok());
+        assert_eq!(value, "secret");
+    }
+
+    #[test]
+    fn read_secret_file_rejects_empty_file() {
+        let mut value = String::new();
+        assert!(read_secret_file("password", &mut value, &Some("/dev/null".to_string())).is_err());
+    }
+
+    #[test]
+    fn read_secret_file_rejects_both_value_and_file() {
+        let mut value = "secret".to_string();
+        assert!(read_secret_file("password", &mut value, &Some("/dev/null".to_string())).is_err());
+    }
+
+    #[test]
+    fn validate_without_server_files_skips_missing_files() {
+        let config = Config::parse(
+            r#"
+url = "http://localhost:8080"
+[server]
+frontend_dir = "/nonexistent"
+[server.tls]
+cert_file = "/nonexistent/cert.pem"
+key_file = "/nonexistent/key.pem"
+[storage]
+path = "/nonexistent/db.sqlite"
+[oidc]
+provider_url = "https://example.com/"
+client_id = "id"
+client_secret = "secret"
+role_attribute_path = "`true`"
+[[drivers]]
+type = "Debug"
+name = "debug01"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.validate().len(), 4);
+        assert!(config.validate_with(false).is_empty());
+    }
+
+    #[test]
+    fn validate_reports_invalid_debug_driver_settings() {
+        let config = Config::parse(
+            r#"
+url = "http://localhost:8080"
+[oidc]
+provider_url = "https://example.com/"
+client_id = "id"
+client_secret = "secret"
+role_attribute_path = "`true`"
+[[drivers]]
+type = "Debug"
+name = "debug01"
+failure_rate = 1.5
+"#,
+        )
+        .unwrap();
+        let problems = config.validate();
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].message.contains("failure_rate"),
+            "{}",
+            problems[0].message
+        );
+    }
+
+    #[test]
+    fn check_config_reports_all_problems_of_file() {
+        let path = std::env::temp_dir().join(format!(
+            "machine-launcher-check-config-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"
+url = "not a url"
+[oidc]
+provider_url = "https://example.com/"
+client_id = "id"
+client_secret = "secret"
+role_attribute_path = "contains(roles"
+[[drivers]]
+type = "Wol"
+name = "wol01"
+mac_addr = "00:11:22"
+ip_addr = "192.0.2.1"
+"#,
+        )
+        .unwrap();
+        let problems = check_config(&path);
+        std::fs::remove_file(&path).unwrap();
+        let locations: Vec<&str> = problems.iter().map(|p| p.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "url",
+                "oidc.role_attribute_path",
+                "drivers[0] (name \"wol01\")"
+            ],
+            "{:?}",
+            problems
+        );
+
+        let problems = check_config("/nonexistent/config.toml");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].location, "/nonexistent/config.toml");
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_and_invalid_driver_names() {
+        let config = Config::parse(
+            r#"
+url = "http://localhost:8080"
+[oidc]
+provider_url = "https://example.com/"
+client_id = "id"
+client_secret = "secret"
+role_attribute_path = "`true`"
+[[drivers]]
+type = "Debug"
+name = "debug01"
+[[drivers]]
+type = "Debug"
+name = "debug01"
+[[drivers]]
+type = "Debug"
+name = "debug/02"
+[[drivers]]
+type = "Debug"
+name = "debug-03.lan"
+"#,
+        )
+        .unwrap();
+        let problems: Vec<String> = config.validate().iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("drivers[1] (name \"debug01\")"));
+        assert!(problems[0].contains("duplicated with drivers[0]"));
+        assert!(problems[1].starts_with("drivers[2] (name \"debug/02\")"));
+        assert!(problems[1].contains("hostname format"));
+    }
 }
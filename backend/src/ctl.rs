@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use clap::{Subcommand, ValueEnum};
+use machine_launcher_utils::{format_machine_table, MachineRow};
+
+use crate::cmd::{BoxError, Config};
+use crate::drivers::new_driver;
+use crate::drivers::traits::{PowerManagerTrait, PowerStatus};
+use crate::handlers_app::{unknown_status, MachineStatusResponseOne};
+use crate::policy::Action;
+
+#[derive(Subcommand)]
+pub enum CtlCommand {
+    /// List all machines with their status
+    List,
+    /// Show the status of the machine
+    Status {
+        /// Machine name
+        name: String,
+    },
+    /// Start the machine
+    Start {
+        /// Machine name
+        name: String,
+    },
+    /// Stop the machine
+    Stop {
+        /// Machine name
+        name: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Run the command with drivers built in-process, bypassing HTTP and authentication.
+pub fn run(config_path: &str, output: OutputFormat, command: CtlCommand) -> Result<(), BoxError> {
+    // MEMO: ctl may run on another host than the server, without its TLS certificates and so on.
+    let config = Config::load_without_server_files(config_path)?;
+    let is_list = matches!(command, CtlCommand::List);
+
+    // Machines whose status cannot be got. They are listed as unknown like the web server does.
+    let mut failed = 0;
+    let statuses: Vec<MachineStatusResponseOne> = match command {
+        CtlCommand::List => {
+            let mut statuses = vec![];
+            for driver_conf in &config.drivers {
+                let status = build_driver(&config, driver_conf.name())
+                    .and_then(|driver| Ok(driver.status()?));
+                statuses.push(match status {
+                    Ok(status) => response(status),
+                    Err(e) => {
+                        failed += 1;
+                        let mut status = unknown_status(driver_conf, &e);
+                        status.actions = Action::OPERATIONS.to_vec();
+                        status
+                    }
+                });
+            }
+            statuses
+        }
+        CtlCommand::Status { name } => {
+            let driver = build_driver(&config, &name)?;
+            vec![response(driver.status()?)]
+        }
+        CtlCommand::Start { name } => {
+            let driver = build_driver(&config, &name)?;
+            driver.start()?;
+            vec![response(driver.status()?)]
+        }
+        CtlCommand::Stop { name } => {
+            let driver = build_driver(&config, &name)?;
+            driver.stop()?;
+            vec![response(driver.status()?)]
+        }
+    };
+
+    match output {
+        OutputFormat::Json if is_list => {
+            println!("{}", serde_json::to_string_pretty(&statuses)?)
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&statuses[0])?),
+        OutputFormat::Table => print_table(&statuses),
+    }
+    if failed > 0 {
+        return Err(format!("failed to get status of {} machines", failed).into());
+    }
+    Ok(())
+}
+
+// MEMO: all operations are listed, because ctl runs on the host without authorization.
+fn response(status: PowerStatus) -> MachineStatusResponseOne {
+    let mut response: MachineStatusResponseOne = status.into();
+    response.actions = Action::OPERATIONS.to_vec();
+    response
+}
+
+// Build only the driver of `name`, not to connect to other machines.
+fn build_driver(config: &Config, name: &str) -> Result<Arc<dyn PowerManagerTrait>, BoxError> {
+    let driver_conf = config
+        .drivers
+        .iter()
+        .find(|c| c.name() == name)
+        .ok_or_else(|| format!("machine {:?} is not found", name))?;
+    new_driver(driver_conf)
+        .map_err(|e| format!("driver {:?} could not be initialized: {}", name, e).into())
+}
+
+fn print_table(statuses: &[MachineStatusResponseOne]) {
+    print!(
+        "{}",
+        format_machine_table(statuses.iter().map(|s| MachineRow {
+            name: &s.name,
+            hostname: &s.hostname,
+            status: s.status.as_str(),
+            reason: s.reason.as_deref(),
+        }))
+    );
+}
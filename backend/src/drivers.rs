@@ -1,4 +1,30 @@
+use std::sync::Arc;
+
+use crate::cmd::DriverType;
+use debug::DebugDriver;
+use ipmi::IpmiDriver;
+use traits::PowerManagerTrait;
+use wake_on_lan::WakeOnLanDriver;
+
 pub mod debug;
 pub mod ipmi;
 pub mod traits;
 pub mod wake_on_lan;
+
+pub fn new_driver(
+    driver_conf: &DriverType,
+) -> Result<Arc<dyn PowerManagerTrait>, Box<dyn std::error::Error>> {
+    Ok(match driver_conf.clone() {
+        DriverType::Debug(c) => Arc::new(DebugDriver::new(c.name, c.behavior, c.metadata)?),
+        DriverType::Ipmi(c) => Arc::new(IpmiDriver::new(
+            c.name,
+            c.server_addr,
+            c.username,
+            c.password,
+            c.metadata,
+        )?),
+        DriverType::Wol(c) => Arc::new(WakeOnLanDriver::new(
+            c.name, c.mac_addr, c.ip_addr, c.metadata,
+        )?),
+    })
+}
@@ -1,38 +1,288 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
+use rand::Rng;
+
+use crate::cmd::{DebugBehavior, DebugOperation, DebugPowerState, MachineMetadata};
 use crate::{Error, PowerManagerTrait, PowerStatus};
 
+#[derive(Debug, Clone, Copy)]
+enum DebugState {
+    Stopped,
+    Booting { until: Instant },
+    Running,
+    ShuttingDown { until: Instant },
+}
+impl DebugState {
+    // Move to the next state if the transition has been finished.
+    fn settle(self, now: Instant) -> Self {
+        match self {
+            DebugState::Booting { until } if until <= now => DebugState::Running,
+            DebugState::ShuttingDown { until } if until <= now => DebugState::Stopped,
+            s => s,
+        }
+    }
+}
+
+// MEMO: the simulated power state is not persisted, so that each start of the server begins
+// from `initial_state` as the tests and demos using this driver expect.
 #[derive(Debug, Clone)]
 pub struct DebugDriver {
     name: String,
-    status: Arc<RwLock<bool>>,
+    state: Arc<RwLock<DebugState>>,
+    // Number of calls so far, to apply `fail_on` in order
+    calls: Arc<AtomicUsize>,
+    behavior: DebugBehavior,
+    metadata: MachineMetadata,
 }
 impl DebugDriver {
-    pub fn new(name: String) -> Self {
-        DebugDriver {
+    pub fn new(
+        name: String,
+        behavior: DebugBehavior,
+        metadata: MachineMetadata,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // MEMO: reported by the config validation, and checked again here because `gen_bool`
+        // panics out of the range.
+        if !(0.0..=1.0).contains(&behavior.failure_rate) {
+            return Err(format!(
+                "failure_rate must be between 0.0 and 1.0, but {}",
+                behavior.failure_rate
+            )
+            .into());
+        }
+        let state = match behavior.initial_state {
+            DebugPowerState::Running => DebugState::Running,
+            DebugPowerState::Stopped => DebugState::Stopped,
+        };
+        Ok(DebugDriver {
             name,
-            status: Arc::new(RwLock::new(false)),
+            state: Arc::new(RwLock::new(state)),
+            calls: Arc::new(AtomicUsize::new(0)),
+            behavior,
+            metadata,
+        })
+    }
+
+    fn inject_fault(&self, operation: DebugOperation) -> Result<(), Error> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        let failed = match self.behavior.fail_on.get(call) {
+            Some(scripted) => scripted.matches(operation),
+            None => {
+                self.behavior.always_fail.contains(&operation)
+                    || rand::thread_rng().gen_bool(self.behavior.failure_rate)
+            }
+        };
+        if failed {
+            tracing::warn!("{}: injected failure on {:?}", self.name, operation);
+            return Err(Error::InternalServerError(
+                format!("DebugDriver: injected failure on {:?}", operation).into(),
+            ));
         }
+        Ok(())
     }
 }
+// Wait like drivers waiting for responses of machines.
+// MEMO: drivers are called from async code (e.g. `ctl`), so the runtime is told to move other
+// tasks off this thread while waiting.
+fn simulate_latency(latency: Duration) {
+    if latency.is_zero() {
+        return;
+    }
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| std::thread::sleep(latency))
+        }
+        _ => std::thread::sleep(latency),
+    }
+}
+
 impl PowerManagerTrait for DebugDriver {
     fn start(&self) -> Result<(), Error> {
-        println!("{:?}: start", self.name);
-        *self.status.write().unwrap() = true;
+        tracing::info!("{}: start", self.name);
+        self.inject_fault(DebugOperation::Start)?;
+        let mut state = self.state.write().unwrap();
+        let now = Instant::now();
+        *state = match state.settle(now) {
+            DebugState::Stopped | DebugState::ShuttingDown { .. } => DebugState::Booting {
+                until: now + Duration::from_secs(self.behavior.boot_duration_secs),
+            },
+            s => s,
+        }
+        .settle(now);
         Ok(())
     }
     fn status(&self) -> Result<PowerStatus, Error> {
-        println!("{:?}: status", self.name);
+        tracing::debug!("{}: status", self.name);
+        simulate_latency(Duration::from_millis(self.behavior.status_latency_ms));
+        self.inject_fault(DebugOperation::Status)?;
+        let now = Instant::now();
+        let state = {
+            let mut state = self.state.write().unwrap();
+            *state = state.settle(now);
+            *state
+        };
+        let (running, reason) = match state {
+            DebugState::Stopped => (false, None),
+            DebugState::Booting { until } => (
+                false,
+                Some(format!(
+                    "booting ({:.0}s remaining)",
+                    until.saturating_duration_since(now).as_secs_f64().ceil()
+                )),
+            ),
+            DebugState::Running => (true, None),
+            DebugState::ShuttingDown { until } => (
+                true,
+                Some(format!(
+                    "shutting down ({:.0}s remaining)",
+                    until.saturating_duration_since(now).as_secs_f64().ceil()
+                )),
+            ),
+        };
         Ok(PowerStatus {
             name: self.name.clone(),
-            hostname: String::from("dummy1.example.com"),
-            running: *self.status.read().unwrap(),
-            reason: None,
+            hostname: self
+                .metadata
+                .hostname
+                .clone()
+                .unwrap_or_else(|| String::from("dummy1.example.com")),
+            running,
+            reason,
+            metadata: self.metadata.clone(),
         })
     }
     fn stop(&self) -> Result<(), Error> {
-        println!("{:?}: stop", self.name);
-        *self.status.write().unwrap() = false;
+        tracing::info!("{}: stop", self.name);
+        self.inject_fault(DebugOperation::Stop)?;
+        let mut state = self.state.write().unwrap();
+        let now = Instant::now();
+        *state = match state.settle(now) {
+            DebugState::Running | DebugState::Booting { .. } => DebugState::ShuttingDown {
+                until: now + Duration::from_secs(self.behavior.shutdown_duration_secs),
+            },
+            s => s,
+        }
+        .settle(now);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::DebugScriptedFailure;
+
+    fn driver(behavior: DebugBehavior) -> DebugDriver {
+        DebugDriver::new("debug01".to_string(), behavior, MachineMetadata::default()).unwrap()
+    }
+
+    #[test]
+    fn settle_finishes_transitions_after_their_durations() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(10);
+        let booting = DebugState::Booting { until: later };
+        assert!(matches!(booting.settle(now), DebugState::Booting { .. }));
+        assert!(matches!(booting.settle(later), DebugState::Running));
+        let shutting_down = DebugState::ShuttingDown { until: later };
+        assert!(matches!(
+            shutting_down.settle(now),
+            DebugState::ShuttingDown { .. }
+        ));
+        assert!(matches!(shutting_down.settle(later), DebugState::Stopped));
+    }
+
+    #[test]
+    fn start_boots_and_stop_shuts_down() {
+        let driver = driver(DebugBehavior {
+            boot_duration_secs: 3600,
+            shutdown_duration_secs: 3600,
+            ..Default::default()
+        });
+        assert!(!driver.status().unwrap().running);
+
+        // Booting is reported as not running
+        driver.start().unwrap();
+        let status = driver.status().unwrap();
+        assert!(!status.running);
+        assert!(status.reason.unwrap().starts_with("booting"));
+        *driver.state.write().unwrap() = DebugState::Booting {
+            until: Instant::now(),
+        };
+        let status = driver.status().unwrap();
+        assert!(status.running);
+        assert!(status.reason.is_none());
+
+        // ShuttingDown is reported as running
+        driver.stop().unwrap();
+        let status = driver.status().unwrap();
+        assert!(status.running);
+        assert!(status.reason.unwrap().starts_with("shutting down"));
+        *driver.state.write().unwrap() = DebugState::ShuttingDown {
+            until: Instant::now(),
+        };
+        let status = driver.status().unwrap();
+        assert!(!status.running);
+        assert!(status.reason.is_none());
+    }
+
+    #[test]
+    fn transitions_without_durations_finish_immediately() {
+        let driver = driver(DebugBehavior::default());
+        driver.start().unwrap();
+        assert!(driver.status().unwrap().running);
+        driver.stop().unwrap();
+        assert!(!driver.status().unwrap().running);
+    }
+
+    #[test]
+    fn fail_on_fails_calls_in_order() {
+        let driver = driver(DebugBehavior {
+            fail_on: vec![
+                DebugScriptedFailure::Status,
+                DebugScriptedFailure::Status,
+                DebugScriptedFailure::None,
+                DebugScriptedFailure::Any,
+            ],
+            ..Default::default()
+        });
+        assert!(driver.status().is_err());
+        // the scripted operation does not match
+        assert!(driver.start().is_ok());
+        assert!(driver.status().is_ok());
+        assert!(driver.stop().is_err());
+        // calls after the script pass
+        assert!(driver.status().is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn status_latency_waits_on_runtime_and_blocking_threads() {
+        let driver = driver(DebugBehavior {
+            status_latency_ms: 10,
+            ..Default::default()
+        });
+        let started_at = Instant::now();
+        assert!(driver.status().is_ok());
+        let blocking = driver.clone();
+        tokio::task::spawn_blocking(move || blocking.status())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(started_at.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn always_fail_fails_every_call_of_the_operation() {
+        let driver = driver(DebugBehavior {
+            always_fail: vec![DebugOperation::Start],
+            ..Default::default()
+        });
+        assert!(driver.start().is_err());
+        assert!(driver.start().is_err());
+        assert!(driver.status().is_ok());
+    }
+}
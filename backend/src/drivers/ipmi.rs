@@ -6,6 +6,7 @@ use std::{
 use chrono::Local;
 use rust_ipmi::{IPMIClient, IPMIClientError};
 
+use crate::cmd::MachineMetadata;
 use crate::{Error, PowerManagerTrait, PowerStatus};
 
 const CLIENT_RENEW_PERIOD: i64 = 60;
@@ -21,6 +22,7 @@ pub struct IpmiDriver {
     name: String,
     server_addr: SocketAddr,
     client: Arc<Mutex<IpmiClient>>,
+    metadata: MachineMetadata,
 }
 impl IpmiDriver {
     pub fn new(
@@ -28,6 +30,7 @@ impl IpmiDriver {
         server_addr_unparsed: String,
         username: String,
         password: String,
+        metadata: MachineMetadata,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let server_addr = server_addr_unparsed
             .to_socket_addrs()?
@@ -42,6 +45,7 @@ impl IpmiDriver {
             name,
             server_addr,
             client,
+            metadata,
         })
     }
 }
@@ -75,20 +79,17 @@ impl PowerManagerTrait for IpmiDriver {
                         "Invalid response received: data is empty".into(),
                     ));
                 }
-                match data[0] {
-                    0x01 => Ok(PowerStatus {
-                        name: self.name.clone(),
-                        hostname: self.server_addr.to_string(),
-                        running: true,
-                        reason: None,
-                    }),
-                    _ => Ok(PowerStatus {
-                        name: self.name.clone(),
-                        hostname: self.server_addr.to_string(),
-                        running: false,
-                        reason: None,
-                    }),
-                }
+                Ok(PowerStatus {
+                    name: self.name.clone(),
+                    hostname: self
+                        .metadata
+                        .hostname
+                        .clone()
+                        .unwrap_or_else(|| self.server_addr.to_string()),
+                    running: data[0] == 0x01,
+                    reason: None,
+                    metadata: self.metadata.clone(),
+                })
             }
             Err(e) => Err(Error::from(e)),
         }
@@ -1,3 +1,4 @@
+use crate::cmd::MachineMetadata;
 use crate::Error;
 
 pub trait PowerManagerTrait: Send + Sync {
@@ -11,4 +12,5 @@ pub struct PowerStatus {
     pub hostname: String,
     pub running: bool,
     pub reason: Option<String>,
+    pub metadata: MachineMetadata,
 }
@@ -3,6 +3,7 @@ use ping::Error as PingError;
 use wakey::WakeyError;
 use wakey::WolPacket;
 
+use crate::cmd::MachineMetadata;
 use crate::{Error, PowerManagerTrait, PowerStatus};
 
 impl From<WakeyError> for Error {
@@ -21,12 +22,14 @@ pub struct WakeOnLanDriver {
     name: String,
     client: WolPacket,
     ip_addr: std::net::IpAddr,
+    metadata: MachineMetadata,
 }
 impl WakeOnLanDriver {
     pub fn new<T: AsRef<str>>(
         name: String,
         mac_addr: T,
         ip_addr_unparsed: T,
+        metadata: MachineMetadata,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let ip_addr = ip_addr_unparsed.as_ref().to_string().parse()?;
         let client = WolPacket::from_string(mac_addr, ':')?;
@@ -34,8 +37,16 @@ impl WakeOnLanDriver {
             name,
             client,
             ip_addr,
+            metadata,
         })
     }
+
+    fn hostname(&self) -> String {
+        self.metadata
+            .hostname
+            .clone()
+            .unwrap_or_else(|| self.ip_addr.to_string())
+    }
 }
 impl PowerManagerTrait for WakeOnLanDriver {
     fn start(&self) -> Result<(), Error> {
@@ -59,17 +70,19 @@ impl PowerManagerTrait for WakeOnLanDriver {
         match res {
             Ok(()) => Ok(PowerStatus {
                 name: self.name.clone(),
-                hostname: self.ip_addr.to_string(),
+                hostname: self.hostname(),
                 running: true,
                 reason: None,
+                metadata: self.metadata.clone(),
             }),
             Err(e) => match e {
                 PingError::InternalError => Err(Error::from(e)),
                 _ => Ok(PowerStatus {
                     name: self.name.clone(),
-                    hostname: self.ip_addr.to_string(),
+                    hostname: self.hostname(),
                     running: false,
                     reason: Some(e.to_string()),
+                    metadata: self.metadata.clone(),
                 }),
             },
         }
@@ -1,12 +1,18 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::State,
-    http::StatusCode,
-    routing::{get, put},
+    extract::{Extension, OriginalUri, Path, State},
+    http::{Method, StatusCode},
+    routing::{delete, get, put},
     Json, Router,
 };
 
+use crate::api_tokens::{self, NewApiToken};
+use crate::cmd::DriverType;
+use crate::drivers::traits::{PowerManagerTrait, PowerStatus};
+use crate::middlewares::AuthenticatedUser;
+use crate::policy::{Action, Authorizer};
+use crate::store::{ApiTokenRecord, OperationRecord};
 use crate::{AppState, Error};
 
 pub fn routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
@@ -14,36 +20,177 @@ pub fn routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
         .route("/servers", get(machine_status))
         .route("/servers/start", put(start_machine))
         .route("/servers/stop", put(stop_machine))
+        .route("/me", get(me))
+        .route("/tokens", get(list_tokens).post(create_token))
+        .route("/tokens/{id}", delete(delete_token))
         .route_layer(axum::middleware::from_fn_with_state(
             app_state,
             crate::middlewares::auth_middleware,
         ))
+        .fallback(crate::not_found)
 }
 
 #[derive(Debug, serde::Serialize)]
-struct MachineStatusResponseOne {
-    name: String,
-    hostname: String,
-    running: bool,
-    reason: Option<String>,
+struct MeResponse {
+    sub: String,
+    name: Option<String>,
+    email: Option<String>,
+    picture: Option<String>,
+    // Sent in `X-CSRF-Token` header on changes by the session cookie
+    csrf_token: Option<String>,
 }
 
-async fn machine_status(
-    State(state): State<Arc<AppState>>,
-) -> Result<(StatusCode, Json<Vec<MachineStatusResponseOne>>), Error> {
-    let mut res: Vec<MachineStatusResponseOne> = vec![];
-    for driver in state.drivers.clone().values() {
-        let status = driver.status()?;
-        res.push(MachineStatusResponseOne {
+async fn me(
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<(StatusCode, Json<MeResponse>), Error> {
+    let claim = |key: &str| {
+        user.claims
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+    };
+    Ok((
+        StatusCode::OK,
+        Json(MeResponse {
+            sub: claim("sub").unwrap_or_default(),
+            name: claim("name"),
+            email: claim("email"),
+            picture: claim("picture"),
+            csrf_token: user.csrf_token.clone(),
+        }),
+    ))
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct MachineStatusResponseOne {
+    pub(crate) name: String,
+    pub(crate) display_name: Option<String>,
+    pub(crate) hostname: String,
+    pub(crate) description: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) icon: Option<String>,
+    pub(crate) links: Vec<MachineLinkResponse>,
+    // Same as `status == Running`, kept for clients before `status` is added
+    pub(crate) running: bool,
+    pub(crate) status: MachineState,
+    pub(crate) reason: Option<String>,
+    // Actions the user may do on the machine
+    pub(crate) actions: Vec<Action>,
+}
+// MEMO: no actions are listed, and callers fill in the ones allowed after authorization,
+// not to advertise actions which have not been authorized.
+impl From<PowerStatus> for MachineStatusResponseOne {
+    fn from(status: PowerStatus) -> Self {
+        MachineStatusResponseOne {
             name: status.name,
+            display_name: status.metadata.display_name,
             hostname: status.hostname,
+            description: status.metadata.description,
+            tags: status.metadata.tags,
+            icon: status.metadata.icon,
+            links: status
+                .metadata
+                .links
+                .into_iter()
+                .map(|link| MachineLinkResponse {
+                    name: link.name,
+                    url: link.url,
+                })
+                .collect(),
             running: status.running,
+            status: if status.running {
+                MachineState::Running
+            } else {
+                MachineState::Stopped
+            },
             reason: status.reason,
-        });
+            actions: vec![],
+        }
+    }
+}
+
+/// Power status of the machine, which is unknown if the driver failed to get it.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum MachineState {
+    Running,
+    Stopped,
+    Unknown,
+}
+impl MachineState {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            MachineState::Running => "running",
+            MachineState::Stopped => "stopped",
+            MachineState::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct MachineLinkResponse {
+    pub(crate) name: String,
+    pub(crate) url: String,
+}
+
+async fn machine_status(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthenticatedUser>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+) -> Result<(StatusCode, Json<Vec<MachineStatusResponseOne>>), Error> {
+    let reloadable = state.reloadable();
+    let authorizer = Authorizer::new(reloadable.clone(), &user, &method, uri.path());
+    // MEMO: drivers may block (e.g. ipmitool), so statuses are queried on blocking threads in parallel.
+    let tasks: Vec<_> = reloadable
+        .config
+        .drivers
+        .iter()
+        .filter(|conf| authorizer.is_allowed(conf.name(), Action::View))
+        .filter_map(|conf| {
+            let driver = reloadable.drivers.get(conf.name())?.clone();
+            Some((conf, tokio::task::spawn_blocking(move || driver.status())))
+        })
+        .collect();
+    let mut res: Vec<MachineStatusResponseOne> = vec![];
+    for (conf, task) in tasks {
+        let result = task
+            .await
+            .unwrap_or_else(|e| Err(Error::InternalServerError(e.to_string().into())));
+        let mut status: MachineStatusResponseOne = match result {
+            Ok(status) => status.into(),
+            Err(e) => {
+                tracing::warn!("failed to get status of {}: {}", conf.name(), e);
+                unknown_status(conf, &e)
+            }
+        };
+        status.actions = authorizer.allowed_operations(conf.name());
+        res.push(status);
     }
     Ok((StatusCode::OK, Json(res)))
 }
 
+// Status of the machine whose driver failed to answer. The error is shown as `reason`.
+pub(crate) fn unknown_status(
+    conf: &DriverType,
+    e: &dyn std::fmt::Display,
+) -> MachineStatusResponseOne {
+    let metadata = conf.metadata().clone();
+    let mut status: MachineStatusResponseOne = PowerStatus {
+        name: conf.name().to_string(),
+        hostname: metadata
+            .hostname
+            .clone()
+            .unwrap_or_else(|| conf.name().to_string()),
+        running: false,
+        reason: Some(format!("failed to get status: {}", e)),
+        metadata,
+    }
+    .into();
+    status.status = MachineState::Unknown;
+    status
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct StartMachineRequest {
     name: String,
@@ -51,24 +198,21 @@ struct StartMachineRequest {
 
 async fn start_machine(
     State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthenticatedUser>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
     Json(req): Json<StartMachineRequest>,
 ) -> Result<(StatusCode, Json<MachineStatusResponseOne>), Error> {
-    match state.drivers.get(&req.name) {
-        Some(driver) => {
-            driver.start()?;
-            let status = driver.status()?;
-            Ok((
-                StatusCode::ACCEPTED,
-                Json(MachineStatusResponseOne {
-                    name: status.name,
-                    hostname: status.hostname,
-                    running: status.running,
-                    reason: status.reason,
-                }),
-            ))
-        }
-        None => Err(Error::NotFound("driver is not found".into())),
-    }
+    let authorizer = Authorizer::new(state.reloadable(), &user, &method, uri.path());
+    let driver = authorized_driver(&state, &authorizer, &req.name, Action::Start)?;
+    let status = run_operation(&state, req.name.clone(), "start", move || {
+        driver.start()?;
+        driver.status()
+    })
+    .await?;
+    let mut status: MachineStatusResponseOne = status.into();
+    status.actions = authorizer.allowed_operations(&req.name);
+    Ok((StatusCode::ACCEPTED, Json(status)))
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -78,22 +222,184 @@ struct StopMachineRequest {
 
 async fn stop_machine(
     State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthenticatedUser>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
     Json(req): Json<StopMachineRequest>,
 ) -> Result<(StatusCode, Json<MachineStatusResponseOne>), Error> {
-    match state.drivers.get(&req.name) {
-        Some(driver) => {
-            driver.stop()?;
-            let status = driver.status()?;
-            Ok((
-                StatusCode::ACCEPTED,
-                Json(MachineStatusResponseOne {
-                    name: status.name,
-                    hostname: status.hostname,
-                    running: status.running,
-                    reason: status.reason,
-                }),
-            ))
+    let authorizer = Authorizer::new(state.reloadable(), &user, &method, uri.path());
+    let driver = authorized_driver(&state, &authorizer, &req.name, Action::Stop)?;
+    let status = run_operation(&state, req.name.clone(), "stop", move || {
+        driver.stop()?;
+        driver.status()
+    })
+    .await?;
+    let mut status: MachineStatusResponseOne = status.into();
+    status.actions = authorizer.allowed_operations(&req.name);
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ApiTokenResponse {
+    id: i64,
+    name: String,
+    read_only: bool,
+    machines: Option<Vec<String>>,
+    created_at: String,
+    expires_at: String,
+    last_used_at: Option<String>,
+}
+impl From<ApiTokenRecord> for ApiTokenResponse {
+    fn from(token: ApiTokenRecord) -> Self {
+        let rfc3339 = |t: i64| {
+            chrono::DateTime::from_timestamp(t, 0)
+                .unwrap_or_default()
+                .to_rfc3339()
+        };
+        ApiTokenResponse {
+            id: token.id,
+            name: token.name,
+            read_only: token.read_only,
+            machines: token.machines,
+            created_at: rfc3339(token.created_at),
+            expires_at: rfc3339(token.expires_at),
+            last_used_at: token.last_used_at.map(rfc3339),
         }
-        None => Err(Error::NotFound("driver is not found".into())),
     }
 }
+
+#[derive(Debug, serde::Serialize)]
+struct CreatedApiTokenResponse {
+    // The token itself, which cannot be shown again
+    token: String,
+    #[serde(flatten)]
+    api_token: ApiTokenResponse,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CreateApiTokenRequest {
+    name: String,
+    #[serde(default)]
+    read_only: bool,
+    machines: Option<Vec<String>>,
+    expires_in_secs: u64,
+}
+
+// MEMO: API tokens cannot manage API tokens, not to extend their scope or lifetime by themselves.
+fn reject_api_token(user: &AuthenticatedUser) -> Result<(), Error> {
+    if user.scope.is_some() {
+        return Err(Error::Forbidden(
+            "API tokens cannot be managed with API tokens".into(),
+        ));
+    }
+    Ok(())
+}
+
+async fn list_tokens(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<(StatusCode, Json<Vec<ApiTokenResponse>>), Error> {
+    reject_api_token(&user)?;
+    let tokens = api_tokens::list_api_tokens(&state, &user).await?;
+    Ok((
+        StatusCode::OK,
+        Json(tokens.into_iter().map(ApiTokenResponse::from).collect()),
+    ))
+}
+
+async fn create_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(req): Json<CreateApiTokenRequest>,
+) -> Result<(StatusCode, Json<CreatedApiTokenResponse>), Error> {
+    reject_api_token(&user)?;
+    // MEMO: users without roles are rejected by `auth_middleware`, and tokens are still
+    // authorized on each use.
+    let (token, record) = api_tokens::create_api_token(
+        &state,
+        &user,
+        NewApiToken {
+            name: req.name,
+            read_only: req.read_only,
+            machines: req.machines,
+            expires_in_secs: req.expires_in_secs,
+        },
+    )
+    .await?;
+    tracing::info!("API token {:?} of {} is created", record.name, record.owner);
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiTokenResponse {
+            token,
+            api_token: record.into(),
+        }),
+    ))
+}
+
+async fn delete_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, Error> {
+    reject_api_token(&user)?;
+    api_tokens::delete_api_token(&state, &user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Get the driver of `name` if the user may do `action` on it. Machines the user cannot see
+// are reported as not found, not to reveal them.
+fn authorized_driver(
+    state: &AppState,
+    authorizer: &Authorizer,
+    name: &str,
+    action: Action,
+) -> Result<Arc<dyn PowerManagerTrait>, Error> {
+    let driver = state
+        .reloadable()
+        .drivers
+        .get(name)
+        .filter(|_| authorizer.is_allowed(name, Action::View))
+        .cloned()
+        .ok_or_else(|| Error::NotFound("driver is not found".into()))?;
+    if !authorizer.is_allowed(name, action) {
+        return Err(Error::Forbidden(
+            format!("you are not allowed to {} {}", action.as_str(), name).into(),
+        ));
+    }
+    Ok(driver)
+}
+
+// Run the driver operation on a blocking thread. It is not cut off even if the client
+// disconnects, and it is waited for on shutdown. The result is recorded to the store.
+async fn run_operation<F>(
+    state: &AppState,
+    machine: String,
+    action: &'static str,
+    f: F,
+) -> Result<PowerStatus, Error>
+where
+    F: FnOnce() -> Result<PowerStatus, Error> + Send + 'static,
+{
+    let guard = state
+        .shutdown
+        .begin_operation(format!("{} {}", action, machine));
+    let store = state.store.clone();
+    tokio::task::spawn_blocking(move || {
+        let _guard = guard;
+        let started_at = chrono::Utc::now().timestamp();
+        let result = f();
+        let record = OperationRecord {
+            machine,
+            action: action.to_string(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            started_at,
+            finished_at: chrono::Utc::now().timestamp(),
+        };
+        if let Err(e) = store.record_operation(&record) {
+            tracing::warn!("failed to record operation {:?}: {}", record, e);
+        }
+        result
+    })
+    .await
+    .map_err(|e| Error::InternalServerError(format!("operation failed: {}", e).into()))?
+}
@@ -1,33 +1,198 @@
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use axum::{
-    extract::{Query, State},
+    extract::{ConnectInfo, Path, Query, State},
+    http::HeaderMap,
     response::Redirect,
-    routing::get,
-    Router,
+    routing::{get, post},
+    Form, Json, Router,
 };
 use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
-use openidconnect::core::CoreResponseType;
+use openidconnect::core::{CoreIdToken, CoreResponseType, CoreRevocableToken};
 use openidconnect::{
-    AuthenticationFlow, AuthorizationCode, CsrfToken, Nonce, PkceCodeChallenge, Scope,
-    TokenResponse,
+    AuthenticationFlow, AuthorizationCode, CsrfToken, LogoutRequest, Nonce, OAuth2TokenResponse,
+    PkceCodeChallenge, PostLogoutRedirectUrl, RefreshToken, RevocationUrl, Scope, TokenResponse,
 };
+use serde::{Deserialize, Serialize};
+use url::Url;
 
+use crate::cmd::LOCAL_PROVIDER_NAME;
+use crate::local_auth;
+use crate::middlewares::{check_csrf_token, verify_same_origin};
+use crate::oidc::OidcProvider;
+use crate::pending_logins::PendingLogin;
+use crate::rate_limit;
+use crate::sessions::{create_session, csrf_token, take_session};
 use crate::{AppState, Error};
-use machine_launcher_utils::COOKIE_KEY;
+use machine_launcher_utils::{all_claims_from_jwt, COOKIE_KEY};
+
+// Upper bound of `return_to`, not to keep long strings in pending logins
+const MAX_RETURN_TO_LEN: usize = 2048;
 
 pub fn routes() -> Router<Arc<AppState>> {
     Router::new()
+        .route("/providers", get(list_providers))
         .route("/login", get(login))
-        .route("/logout", get(logout))
+        .route("/login/{provider}", get(login_with))
+        .route("/local/login", post(local_login))
+        .route("/logout", post(logout))
         .route("/callback", get(callback))
+        .fallback(crate::not_found)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ProviderKind {
+    Oidc,
+    Local,
+}
+
+#[derive(Debug, Serialize)]
+struct ProviderResponse {
+    name: String,
+    display_name: String,
+    kind: ProviderKind,
+}
+
+// OIDC providers to choose from on the login page.
+async fn list_providers(State(state): State<Arc<AppState>>) -> Json<Vec<ProviderResponse>> {
+    let reloadable = state.reloadable();
+    let mut providers: Vec<ProviderResponse> = state
+        .oidc_providers
+        .all()
+        .iter()
+        .map(|provider| ProviderResponse {
+            name: provider.name.clone(),
+            display_name: reloadable
+                .config
+                .oidc_configs()
+                .find(|c| c.name == provider.name)
+                .and_then(|c| c.display_name.clone())
+                .unwrap_or_else(|| provider.name.clone()),
+            kind: ProviderKind::Oidc,
+        })
+        .collect();
+    if let Some(local_auth) = reloadable
+        .config
+        .local_auth
+        .as_ref()
+        .filter(|_| local_auth::is_enabled(&state))
+    {
+        providers.push(ProviderResponse {
+            name: LOCAL_PROVIDER_NAME.to_string(),
+            display_name: local_auth.display_name.clone(),
+            kind: ProviderKind::Local,
+        });
+    }
+    Json(providers)
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    // Path to return to after login, e.g. `/#/tokens`
+    return_to: Option<String>,
+}
+
+// Log in with the only OIDC provider, or let users choose one (or enter the password of
+// the local account) on the top page, which passes `return_to` to the login.
+async fn login(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<LoginQuery>,
+) -> Result<Redirect, Error> {
+    let client = client_ip(&state, peer, &headers);
+    let return_to = return_path(&state, query.return_to.as_deref());
+    match state.oidc_providers.all().as_slice() {
+        [provider] if !local_auth::is_enabled(&state) => {
+            start_login(&state, client, provider, return_to)
+        }
+        _ if return_to == "/" => Ok(Redirect::to("/")),
+        _ => Ok(Redirect::to(&format!(
+            "/?{}",
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("return_to", &return_to)
+                .finish()
+        ))),
+    }
+}
+
+async fn login_with(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(provider): Path<String>,
+    Query(query): Query<LoginQuery>,
+) -> Result<Redirect, Error> {
+    let client = client_ip(&state, peer, &headers);
+    let provider = state.oidc_providers.get(&provider).ok_or_else(|| {
+        Error::NotFound(format!("OIDC provider {:?} is not available", provider).into())
+    })?;
+    let return_to = return_path(&state, query.return_to.as_deref());
+    start_login(&state, client, &provider, return_to)
 }
 
-async fn login(State(state): State<Arc<AppState>>) -> Result<Redirect, Error> {
+// The path of `return_to` if it is safe to redirect to after login, otherwise `/`.
+// MEMO: only the top page of this server is allowed, not to be used as an open redirect to
+// phishing sites, nor to log out or loop right after login by paths under `/auth/`.
+// Pages of the frontend are switched by the fragment, which is kept.
+fn return_path(state: &AppState, return_to: Option<&str>) -> String {
+    let url = state.reloadable().config.url.clone();
+    return_to
+        .and_then(|r| {
+            let path = safe_return_path(&url, r);
+            if path.is_none() {
+                tracing::debug!("return_to {:?} is ignored", r);
+            }
+            path
+        })
+        .unwrap_or_else(|| "/".to_string())
+}
+
+fn safe_return_path(url: &str, return_to: &str) -> Option<String> {
+    // `//host/...` and `/\host/...` are taken as other hosts by browsers
+    if return_to.len() > MAX_RETURN_TO_LEN
+        || !return_to.starts_with('/')
+        || return_to.starts_with("//")
+        || return_to.contains('\\')
+        || return_to.chars().any(|c| c.is_control())
+    {
+        return None;
+    }
+    let base = Url::parse(url).ok()?;
+    let url = base.join(return_to).ok()?;
+    // MEMO: check the normalized path, e.g. `/x/../auth/logout`.
+    (url.origin() == base.origin() && url.path() == "/").then(|| match url.fragment() {
+        Some(fragment) => format!("/#{}", fragment),
+        None => "/".to_string(),
+    })
+}
+
+// The address of the client to rate-limit by, which is the one behind trusted proxies.
+fn client_ip(state: &AppState, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let reloadable = state.reloadable();
+    rate_limit::client_ip(&reloadable.config.server.trusted_proxies, peer, headers)
+}
+
+fn start_login(
+    state: &AppState,
+    client: IpAddr,
+    provider: &OidcProvider,
+    return_to: String,
+) -> Result<Redirect, Error> {
+    // MEMO: each login keeps a pending login in memory until it expires.
+    if !state.login_limiter.check(&rate_limit::client_key(client)) {
+        tracing::warn!("too many logins are started from {}", client);
+        return Err(Error::TooManyRequests(
+            "too many logins are started, try again later".into(),
+        ));
+    }
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-    let (auth_url, csrf_token, _nonce) = state
-        .oidc_client
+    let mut auth_request = provider
+        .client
         .authorize_url(
             AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
             CsrfToken::new_random,
@@ -38,24 +203,195 @@ async fn login(State(state): State<Arc<AppState>>) -> Result<Redirect, Error> {
             Scope::new("profile".to_string()),
             Scope::new("email".to_string()),
         ])
-        .set_pkce_challenge(pkce_challenge)
-        .url();
+        .set_pkce_challenge(pkce_challenge);
+    if provider.offline_access {
+        // to renew the session by the refresh token
+        auth_request = auth_request.add_scope(Scope::new("offline_access".to_string()));
+    }
+    let (auth_url, csrf_token, nonce) = auth_request.url();
 
-    state
-        .pkce_verifiers
-        .lock()
-        .unwrap()
-        .insert(csrf_token.secret().clone(), pkce_verifier);
+    state.pending_logins.insert(
+        csrf_token.secret().clone(),
+        PendingLogin {
+            provider: provider.name.clone(),
+            pkce_verifier,
+            nonce,
+            return_to,
+        },
+    );
 
     Ok(Redirect::temporary(auth_url.as_str()))
 }
 
-async fn logout(cookie_jar: CookieJar) -> Result<(CookieJar, Redirect), Error> {
-    // TODO: logout from OIDC Provider using renocation_url
+#[derive(Deserialize)]
+struct LocalLoginRequest {
+    username: String,
+    password: String,
+    return_to: Option<String>,
+}
+
+// Errors of local logins shown on the top page, passed as `login_error` query parameter
+const LOGIN_ERROR_DISABLED: &str = "disabled";
+const LOGIN_ERROR_INVALID: &str = "invalid_credentials";
+const LOGIN_ERROR_THROTTLED: &str = "too_many_attempts";
+
+// Log in with the local account by the form on the top page.
+// MEMO: errors are shown on the top page by redirecting back to it, because this is posted by
+// the form and responses are displayed as they are.
+async fn local_login(
+    cookie_jar: CookieJar,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(req): Form<LocalLoginRequest>,
+) -> Result<(CookieJar, Redirect), Error> {
+    let client = client_ip(&state, peer, &headers);
+    // MEMO: not to log in victims as the account of attackers by forms on other sites
+    verify_same_origin(&state, &headers)?;
+    let return_to = return_path(&state, req.return_to.as_deref());
+    let login_failed = |error: &str| {
+        Redirect::to(&format!(
+            "/?{}",
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("login_error", error)
+                .append_pair("return_to", &return_to)
+                .finish()
+        ))
+    };
+    let config = match state.reloadable().config.local_auth.clone() {
+        Some(config) if local_auth::is_enabled(&state) => config,
+        _ => return Ok((cookie_jar, login_failed(LOGIN_ERROR_DISABLED))),
+    };
+    let throttle = &state.local_login_throttle;
+    let Some(permit) = throttle.try_attempt(&req.username, client) else {
+        tracing::warn!(
+            "login of local account {:?} from {} is throttled",
+            req.username,
+            client
+        );
+        return Ok((cookie_jar, login_failed(LOGIN_ERROR_THROTTLED)));
+    };
+    let session_lifetime_secs = config.session_lifetime_secs;
+    let username = req.username.clone();
+    let claims = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        local_auth::authenticate(&config, &req.username, &req.password)
+    })
+    .await
+    .map_err(|e| {
+        Error::InternalServerError(format!("password verification failed: {}", e).into())
+    })?;
+    let Some(claims) = claims else {
+        tracing::warn!(
+            "failed login of local account {:?} from {}",
+            username,
+            client
+        );
+        return Ok((cookie_jar, login_failed(LOGIN_ERROR_INVALID)));
+    };
+    throttle.record_success(&username, client);
+
+    let expires_at = chrono::Utc::now()
+        .timestamp()
+        .saturating_add(session_lifetime_secs.try_into().unwrap_or(i64::MAX));
+    // MEMO: there is no ID token for local accounts, which is used only on OIDC logout.
+    let session_token = create_session(
+        &state,
+        LOCAL_PROVIDER_NAME,
+        claims,
+        String::new(),
+        None,
+        expires_at,
+    )
+    .await?;
+    Ok((
+        cookie_jar.add(session_cookie(session_token)),
+        Redirect::to(&return_to),
+    ))
+}
+
+#[derive(Deserialize)]
+struct LogoutForm {
+    csrf_token: Option<String>,
+}
+
+// Log out by the form in the header, which has the CSRF token of the session.
+// MEMO: this is POST with the CSRF token like other changes by the session cookie, not to be
+// logged out by links or images on other sites.
+async fn logout(
+    cookie_jar: CookieJar,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(form): Form<LogoutForm>,
+) -> Result<(CookieJar, Redirect), Error> {
+    verify_same_origin(&state, &headers)?;
+    let session = match cookie_jar.get(COOKIE_KEY) {
+        Some(cookie) => {
+            check_csrf_token(
+                form.csrf_token.as_deref().unwrap_or_default(),
+                &csrf_token(cookie.value()),
+            )?;
+            take_session(&state, cookie.value()).await?
+        }
+        None => None,
+    };
     let mut cookie = Cookie::from(COOKIE_KEY);
     cookie.set_max_age(time::Duration::ZERO);
     cookie.set_path("/");
-    Ok((cookie_jar.add(cookie), Redirect::to("/")))
+    let cookie_jar = cookie_jar.add(cookie);
+
+    // MEMO: the provider may have been removed from the config since the login.
+    let Some((session, provider)) = session.and_then(|session| {
+        let provider = state.oidc_providers.get(&session.provider)?;
+        Some((session, provider))
+    }) else {
+        return Ok((cookie_jar, Redirect::to("/")));
+    };
+    if let (Some(revocation_url), Some(refresh_token)) =
+        (&provider.revocation_url, session.refresh_token)
+    {
+        if let Err(e) = revoke_token(&state, &provider, revocation_url, refresh_token).await {
+            tracing::warn!("failed to revoke refresh token: {}", e);
+        }
+    }
+
+    // MEMO: end the session of the OIDC provider too, not to log in again silently by it
+    // (e.g. on shared browsers).
+    let Some(end_session_url) = &provider.end_session_url else {
+        return Ok((cookie_jar, Redirect::to("/")));
+    };
+    let post_logout_redirect_url = Url::parse(&state.reloadable().config.url)
+        .and_then(|url| url.join("/"))
+        .map_err(|e| Error::InternalServerError(format!("invalid url: {}", e).into()))?;
+    let mut logout_request = LogoutRequest::from(end_session_url.clone())
+        .set_client_id(provider.client.client_id().clone())
+        .set_post_logout_redirect_uri(PostLogoutRedirectUrl::from_url(post_logout_redirect_url));
+    if let Ok(id_token) = CoreIdToken::from_str(&session.id_token) {
+        logout_request = logout_request.set_id_token_hint(&id_token);
+    }
+    Ok((
+        cookie_jar,
+        Redirect::to(logout_request.http_get_url().as_str()),
+    ))
+}
+
+async fn revoke_token(
+    state: &AppState,
+    provider: &OidcProvider,
+    revocation_url: &RevocationUrl,
+    refresh_token: String,
+) -> Result<(), Error> {
+    provider
+        .client
+        .clone()
+        .set_revocation_url(revocation_url.clone())
+        .revoke_token(CoreRevocableToken::RefreshToken(RefreshToken::new(
+            refresh_token,
+        )))
+        .map_err(|e| Error::InternalServerError(format!("{:?}", e).into()))?
+        .request_async(state.oidc_providers.http_client())
+        .await
+        .map_err(|e| Error::InternalServerError(format!("{:?}", e).into()))
 }
 
 async fn callback(
@@ -69,29 +405,200 @@ async fn callback(
     let state_param = params
         .get("state")
         .ok_or_else(|| Error::Unauthorized("Missing state parameter".into()))?;
-    let pkce_verifier = state
-        .pkce_verifiers
-        .lock()
-        .unwrap()
-        .remove(state_param)
-        .ok_or_else(|| Error::Unauthorized("Invalid PKCE verifier".into()))?;
-
-    let resp = state
-        .oidc_client
+    // MEMO: unknown states are also reported as expired, because they have been removed
+    // by the cleanup or the restart in most cases.
+    let pending_login = state
+        .pending_logins
+        .take(state_param)
+        .ok_or_else(|| Error::Unauthorized("login expired, please retry".into()))?;
+    let provider = state
+        .oidc_providers
+        .get(&pending_login.provider)
+        .ok_or_else(|| Error::Unauthorized("login expired, please retry".into()))?;
+
+    let resp = provider
+        .client
         .exchange_code(AuthorizationCode::new(code.to_string()))
-        .set_pkce_verifier(pkce_verifier)
-        .request_async(&reqwest::Client::new())
+        .set_pkce_verifier(pending_login.pkce_verifier)
+        .request_async(state.oidc_providers.http_client())
         .await
         .map_err(|e| Error::Unauthorized(format!("Token exchange failed: {:?}", e).into()))?;
     let id_token = resp
         .id_token()
         .ok_or_else(|| Error::Unauthorized("Id token is none".into()))?;
-    let cookie = Cookie::build((COOKIE_KEY, id_token.to_string()))
+    // MEMO: reject ID tokens issued for other login flows (replay/injection)
+    let expires_at = state
+        .oidc_providers
+        .verify_id_token(&provider, id_token, &pending_login.nonce)
+        .await
+        .map_err(|e| Error::Unauthorized(format!("Id token is invalid: {:?}", e).into()))?
+        .expiration()
+        .timestamp();
+
+    // MEMO: CoreIdToken cannot contain custom claims decided dynamically,
+    // so keep all claims in the session for authorization.
+    let id_token = id_token.to_string();
+    let all_claims =
+        all_claims_from_jwt(&id_token).map_err(|e| Error::Unauthorized(e.to_string().into()))?;
+    let session_token = create_session(
+        &state,
+        &provider.name,
+        serde_json::Value::Object(all_claims.into_iter().collect()),
+        id_token,
+        resp.refresh_token().map(|t| t.secret().clone()),
+        expires_at,
+    )
+    .await?;
+
+    Ok((
+        cookie_jar.add(session_cookie(session_token)),
+        Redirect::to(&pending_login.return_to),
+    ))
+}
+
+fn session_cookie(session_token: String) -> Cookie<'static> {
+    Cookie::build((COOKIE_KEY, session_token))
         .path("/")
-        .http_only(false) // for frontend to refer to JWT's profile
+        .http_only(true)
         .same_site(SameSite::Strict)
         .secure(true)
         .build()
-        .into_owned();
-    Ok((cookie_jar.add(cookie), Redirect::to("/")))
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://launcher.example.com";
+
+    #[test]
+    fn safe_return_path_accepts_pages_of_this_server() {
+        assert_eq!(safe_return_path(URL, "/").as_deref(), Some("/"));
+        assert_eq!(
+            safe_return_path(URL, "/#/tokens").as_deref(),
+            Some("/#/tokens")
+        );
+        // only the fragment switches pages
+        assert_eq!(
+            safe_return_path(URL, "/?a=b#/tokens").as_deref(),
+            Some("/#/tokens")
+        );
+        // normalized
+        assert_eq!(
+            safe_return_path(URL, "/x/../#/tokens").as_deref(),
+            Some("/#/tokens")
+        );
+    }
+
+    #[test]
+    fn safe_return_path_rejects_paths_not_served_by_frontend() {
+        assert_eq!(safe_return_path(URL, "/servers/foo"), None);
+        assert_eq!(safe_return_path(URL, "/api/servers?a=b"), None);
+        assert_eq!(safe_return_path(URL, "/index.html"), None);
+    }
+
+    #[test]
+    fn safe_return_path_rejects_other_hosts() {
+        for return_to in [
+            "https://evil.example.com/",
+            "//evil.example.com/",
+            "/\\evil.example.com/",
+            "/\t/evil.example.com/",
+            "evil.example.com",
+            "javascript:alert(1)",
+            "",
+        ] {
+            assert_eq!(safe_return_path(URL, return_to), None, "{:?}", return_to);
+        }
+    }
+
+    #[test]
+    fn safe_return_path_rejects_auth_paths() {
+        assert_eq!(safe_return_path(URL, "/auth/logout"), None);
+        assert_eq!(safe_return_path(URL, "/x/../auth/login"), None);
+        assert_eq!(safe_return_path(URL, "/x/%2e%2e/auth/login"), None);
+    }
+
+    #[test]
+    fn safe_return_path_rejects_long_paths() {
+        let long = format!("/{}", "a".repeat(MAX_RETURN_TO_LEN));
+        assert_eq!(safe_return_path(URL, &long), None);
+    }
+
+    fn app_state() -> Arc<AppState> {
+        let config = crate::cmd::Config::parse(&format!(
+            r#"
+url = "{}"
+drivers = []
+[local_auth]
+[[local_auth.users]]
+username = "admin"
+password_hash = "{}"
+"#,
+            URL,
+            local_auth::hash_password("pass123").unwrap()
+        ))
+        .unwrap();
+        AppState::for_tests(config)
+    }
+
+    // Log in as `username` from `peer`, and return the redirected location and whether the
+    // session cookie is set.
+    async fn try_local_login(
+        state: &Arc<AppState>,
+        peer: &str,
+        username: &str,
+        password: &str,
+    ) -> (String, bool) {
+        let mut headers = HeaderMap::new();
+        headers.insert(axum::http::header::ORIGIN, URL.parse().unwrap());
+        let (cookie_jar, redirect) = local_login(
+            CookieJar::new(),
+            State(state.clone()),
+            ConnectInfo(peer.parse().unwrap()),
+            headers,
+            Form(LocalLoginRequest {
+                username: username.to_string(),
+                password: password.to_string(),
+                return_to: Some("/#/tokens".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        let response = axum::response::IntoResponse::into_response(redirect);
+        let location = response.headers()[axum::http::header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        (location, cookie_jar.get(COOKIE_KEY).is_some())
+    }
+
+    #[tokio::test]
+    async fn local_login_creates_session_for_correct_password() {
+        let state = app_state();
+        let (location, logged_in) =
+            try_local_login(&state, "192.0.2.1:1234", "admin", "pass123").await;
+        assert_eq!(location, "/#/tokens");
+        assert!(logged_in);
+    }
+
+    #[tokio::test]
+    async fn local_login_is_throttled_after_failures() {
+        let state = app_state();
+        for _ in 0..5 {
+            let (location, logged_in) =
+                try_local_login(&state, "192.0.2.1:1234", "admin", "wrong").await;
+            assert!(location.contains(LOGIN_ERROR_INVALID), "{}", location);
+            assert!(!logged_in);
+        }
+        // even the correct password is refused until the window ends
+        let (location, logged_in) =
+            try_local_login(&state, "192.0.2.1:1234", "admin", "pass123").await;
+        assert!(location.contains(LOGIN_ERROR_THROTTLED), "{}", location);
+        assert!(!logged_in);
+        // but not from other addresses
+        let (_, logged_in) = try_local_login(&state, "192.0.2.2:1234", "admin", "pass123").await;
+        assert!(logged_in);
+    }
 }
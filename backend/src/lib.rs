@@ -1,13 +1,26 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
 
 use axum::{http::StatusCode, response::Response};
-use jmespath::Expression;
-use openidconnect::core::CoreClient;
-use openidconnect::{EndpointMaybeSet, EndpointNotSet, EndpointSet, PkceCodeVerifier};
+use openidconnect::core::{
+    CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClient, CoreClientAuthMethod, CoreGrantType,
+    CoreJsonWebKey, CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm,
+    CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType,
+};
+use openidconnect::{
+    AdditionalProviderMetadata, EndpointMaybeSet, EndpointNotSet, EndpointSet,
+    LogoutProviderMetadata, ProviderMetadata, RevocationUrl,
+};
 
 use crate::drivers::traits::{PowerManagerTrait, PowerStatus};
+use crate::local_auth::LoginThrottle;
+use crate::oidc::OidcProviders;
+use crate::pending_logins::PendingLogins;
+use crate::rate_limit::RateLimiter;
+use crate::reload::ReloadableState;
+use crate::sessions::RenewLocks;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
 
 pub type OidcClient<HasTokenUrl = EndpointMaybeSet, HasUserInfoUrl = EndpointMaybeSet> = CoreClient<
     EndpointSet,
@@ -18,17 +31,74 @@ pub type OidcClient<HasTokenUrl = EndpointMaybeSet, HasUserInfoUrl = EndpointMay
     HasUserInfoUrl,
 >;
 
+/// Provider metadata including the endpoints used on logout.
+pub type OidcProviderMetadata = ProviderMetadata<
+    LogoutProviderMetadata<RevocationProviderMetadata>,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+/// The token revocation endpoint of the provider metadata. (RFC 7009, RFC 8414)
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct RevocationProviderMetadata {
+    pub revocation_endpoint: Option<RevocationUrl>,
+}
+impl AdditionalProviderMetadata for RevocationProviderMetadata {}
+
 pub struct AppState {
-    pub drivers: HashMap<String, Arc<dyn PowerManagerTrait>>,
-    pub role_attribute_path_expr: Expression<'static>,
-    pub pkce_verifiers: Mutex<HashMap<String, PkceCodeVerifier>>, // Store PKCE verifiers temporarily
-    pub oidc_client: OidcClient<EndpointSet, EndpointMaybeSet>,
+    pub reloadable: RwLock<Arc<ReloadableState>>, // Drivers and others swapped on config reload
+    pub pending_logins: PendingLogins,            // Store PKCE verifiers and nonces temporarily
+    pub oidc_providers: OidcProviders,            // Discovered on startup or in the background
+    pub login_limiter: RateLimiter,               // Logins started per client address
+    pub local_login_throttle: LoginThrottle,      // Login attempts of local accounts
+    pub renew_locks: RenewLocks,                  // Serialize renewals of each session
+    pub shutdown: Shutdown,
+    pub store: Arc<Store>, // Data kept across restarts
+}
+impl AppState {
+    pub fn reloadable(&self) -> Arc<ReloadableState> {
+        self.reloadable.read().unwrap().clone()
+    }
+}
+#[cfg(test)]
+impl AppState {
+    // The state on startup with `config`, with OIDC providers not discovered.
+    pub(crate) fn for_tests(config: cmd::Config) -> Arc<Self> {
+        Arc::new(AppState {
+            oidc_providers: OidcProviders::new(
+                config.oidc_configs().cloned().collect(),
+                config.url.clone(),
+            )
+            .unwrap(),
+            store: Arc::new(Store::open(&config.storage).unwrap()),
+            reloadable: RwLock::new(Arc::new(ReloadableState::build(config, None).unwrap())),
+            pending_logins: PendingLogins::default(),
+            login_limiter: RateLimiter::new(
+                rate_limit::MAX_LOGIN_STARTS,
+                rate_limit::LOGIN_START_WINDOW,
+            ),
+            local_login_throttle: LoginThrottle::default(),
+            renew_locks: RenewLocks::default(),
+            shutdown: Shutdown::default(),
+        })
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
     #[error("{0}")]
     BadRequest(Cow<'static, str>),
     #[error("session error")]
@@ -39,6 +109,8 @@ pub enum Error {
     Forbidden(Cow<'static, str>),
     #[error("not found: {0}")]
     NotFound(Cow<'static, str>),
+    #[error("too many requests: {0}")]
+    TooManyRequests(Cow<'static, str>),
     #[error("not implemented")]
     NotImplemented(),
     #[error("{0}")]
@@ -51,12 +123,19 @@ impl Error {
             Self::Unauthorized(_) | Self::SessionError => StatusCode::UNAUTHORIZED,
             Self::Forbidden(_) => StatusCode::FORBIDDEN,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
-            Self::Io(_) | Self::NotImplemented() | Self::InternalServerError(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Io(_)
+            | Self::Database(_)
+            | Self::NotImplemented()
+            | Self::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
+/// Fallback of the API routers, not to answer unknown API paths with the frontend.
+pub async fn not_found() -> Error {
+    Error::NotFound("no such endpoint".into())
+}
+
 impl axum::response::IntoResponse for Error {
     fn into_response(self) -> Response {
         #[derive(Debug, serde::Serialize)]
@@ -75,8 +154,21 @@ impl axum::response::IntoResponse for Error {
     }
 }
 
+pub mod api_tokens;
+pub mod assets;
 pub mod cmd;
+pub mod ctl;
 pub mod drivers;
 pub mod handlers_app;
 pub mod handlers_oauth;
+pub mod local_auth;
 pub mod middlewares;
+pub mod oidc;
+pub mod pending_logins;
+pub mod policy;
+pub mod rate_limit;
+pub mod reload;
+pub mod server;
+pub mod sessions;
+pub mod shutdown;
+pub mod store;
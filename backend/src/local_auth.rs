@@ -0,0 +1,248 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::cmd::{BoxError, LocalAuthConfig, LocalUserConfig, LOCAL_PROVIDER_NAME};
+use crate::rate_limit::{client_key, RateLimiter};
+use crate::AppState;
+
+// MEMO: verified for unknown usernames, not to tell them from wrong passwords by the time.
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| hash_password("").unwrap_or_default());
+
+// Login attempts allowed per username from each client address, and per client address in
+// `ATTEMPT_WINDOW`
+// MEMO: attempts are not counted per username alone, not to let anyone lock the user out.
+const MAX_ATTEMPTS_PER_USERNAME: u32 = 5;
+const MAX_ATTEMPTS_PER_ADDRESS: u32 = 20;
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(15 * 60);
+// Passwords verified at once, not to exhaust memory and threads by argon2
+const MAX_CONCURRENT_VERIFICATIONS: usize = 4;
+
+/// Whether local accounts can be used to log in now. With `fallback_only`, they can be used
+/// only while no OIDC provider is reachable.
+pub fn is_enabled(state: &AppState) -> bool {
+    state
+        .reloadable()
+        .config
+        .local_auth
+        .as_ref()
+        .is_some_and(|c| !c.fallback_only || !state.oidc_providers.any_reachable())
+}
+
+/// Login attempts of local accounts, to slow down guessing passwords.
+pub struct LoginThrottle {
+    by_username_and_address: RateLimiter,
+    by_address: RateLimiter,
+    verifications: Arc<Semaphore>,
+}
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        LoginThrottle {
+            by_username_and_address: RateLimiter::new(MAX_ATTEMPTS_PER_USERNAME, ATTEMPT_WINDOW),
+            by_address: RateLimiter::new(MAX_ATTEMPTS_PER_ADDRESS, ATTEMPT_WINDOW),
+            verifications: Arc::new(Semaphore::new(MAX_CONCURRENT_VERIFICATIONS)),
+        }
+    }
+}
+impl LoginThrottle {
+    /// Count a login attempt of `username` from `address`, and return the permit to verify
+    /// the password, or `None` if the attempt is refused for too many attempts or
+    /// verifications in progress. The permit is held until the verification finishes.
+    // MEMO: attempts are counted before verifying, not to let concurrent attempts pass the
+    // limit while the passwords are verified.
+    pub fn try_attempt(&self, username: &str, address: IpAddr) -> Option<OwnedSemaphorePermit> {
+        let address = client_key(address);
+        let allowed = self
+            .by_username_and_address
+            .check(&username_and_address(username, &address));
+        // MEMO: counted even when refused above, to limit guessing usernames from the address.
+        if !self.by_address.check(&address) || !allowed {
+            return None;
+        }
+        self.verifications.clone().try_acquire_owned().ok()
+    }
+
+    /// Forget the attempts of `username` from `address` after a successful login.
+    pub fn record_success(&self, username: &str, address: IpAddr) {
+        self.by_username_and_address
+            .reset(&username_and_address(username, &client_key(address)));
+    }
+
+    pub(crate) fn remove_expired(&self) -> usize {
+        self.by_username_and_address.remove_expired() + self.by_address.remove_expired()
+    }
+}
+
+// MEMO: client keys contain no spaces, so keys of different pairs never collide.
+fn username_and_address(username: &str, address: &str) -> String {
+    format!("{} {}", address, username)
+}
+
+/// Verify the password of `username`, and return the claims of the user if it is correct.
+/// This blocks the thread while hashing the password.
+pub fn authenticate(
+    config: &LocalAuthConfig,
+    username: &str,
+    password: &str,
+) -> Option<serde_json::Value> {
+    let user = config
+        .users
+        .iter()
+        .find(|u| u.username == username && !u.disabled);
+    let hash = user.map_or(DUMMY_HASH.as_str(), |u| u.password_hash.as_str());
+    let verified = PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false);
+    let user = user.filter(|_| verified)?;
+    Some(claims_of(user))
+}
+
+/// The current claims of the local account `username` of the sessions and API tokens,
+/// or `None` if the account has been removed or disabled, which invalidates them.
+pub fn current_claims(
+    config: Option<&LocalAuthConfig>,
+    username: &str,
+) -> Option<serde_json::Value> {
+    config?
+        .users
+        .iter()
+        .find(|u| u.username == username && !u.disabled)
+        .map(claims_of)
+}
+
+fn claims_of(user: &LocalUserConfig) -> serde_json::Value {
+    json!({
+        "iss": LOCAL_PROVIDER_NAME,
+        "sub": user.username,
+        "name": user.username,
+        "roles": user.roles,
+    })
+}
+
+/// Hash the password in PHC string format for `local_auth.users`.
+pub fn hash_password(password: &str) -> Result<String, BoxError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| e.to_string())?
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LocalAuthConfig {
+        let hash = hash_password("pass123").unwrap();
+        toml::from_str(&format!(
+            r#"
+[[users]]
+username = "admin"
+password_hash = "{}"
+roles = ["admin"]
+"#,
+            hash
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn authenticate_returns_claims_for_correct_password() {
+        let claims = authenticate(&config(), "admin", "pass123").unwrap();
+        assert_eq!(claims["iss"], LOCAL_PROVIDER_NAME);
+        assert_eq!(claims["sub"], "admin");
+        assert_eq!(claims["roles"], json!(["admin"]));
+    }
+
+    #[test]
+    fn authenticate_rejects_wrong_password_and_unknown_user() {
+        let config = config();
+        assert!(authenticate(&config, "admin", "wrong").is_none());
+        assert!(authenticate(&config, "admin", "").is_none());
+        assert!(authenticate(&config, "unknown", "pass123").is_none());
+        assert!(authenticate(&config, "unknown", "").is_none());
+    }
+
+    #[test]
+    fn authenticate_rejects_disabled_user() {
+        let mut config = config();
+        config.users[0].disabled = true;
+        assert!(authenticate(&config, "admin", "pass123").is_none());
+    }
+
+    #[test]
+    fn current_claims_are_none_for_removed_or_disabled_users() {
+        let mut config = config();
+        assert_eq!(
+            current_claims(Some(&config), "admin").unwrap()["roles"],
+            json!(["admin"])
+        );
+        assert!(current_claims(Some(&config), "unknown").is_none());
+        assert!(current_claims(None, "admin").is_none());
+        config.users[0].disabled = true;
+        assert!(current_claims(Some(&config), "admin").is_none());
+    }
+
+    #[test]
+    fn throttle_counts_attempts_per_username_and_address() {
+        let throttle = LoginThrottle::default();
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        for _ in 0..MAX_ATTEMPTS_PER_USERNAME {
+            assert!(throttle.try_attempt("admin", address).is_some());
+        }
+        assert!(throttle.try_attempt("admin", address).is_none());
+        // attempts from one address do not lock the user out of others
+        assert!(throttle.try_attempt("admin", other).is_some());
+        assert!(throttle.try_attempt("viewer", address).is_some());
+        throttle.record_success("admin", address);
+        assert!(throttle.try_attempt("admin", address).is_some());
+
+        // 5 + 1 + 1 + 1 attempts from `address` so far
+        for i in 8..MAX_ATTEMPTS_PER_ADDRESS {
+            assert!(throttle
+                .try_attempt(&format!("user{}", i), address)
+                .is_some());
+        }
+        assert!(throttle.try_attempt("viewer", address).is_none());
+        assert!(throttle.try_attempt("viewer", other).is_some());
+    }
+
+    #[test]
+    fn throttle_counts_ipv6_addresses_by_64_prefix() {
+        let throttle = LoginThrottle::default();
+        for i in 0..MAX_ATTEMPTS_PER_USERNAME {
+            let address: IpAddr = format!("2001:db8::{:x}", i + 1).parse().unwrap();
+            assert!(throttle.try_attempt("admin", address).is_some());
+        }
+        let address: IpAddr = "2001:db8::ffff".parse().unwrap();
+        assert!(throttle.try_attempt("admin", address).is_none());
+        let address: IpAddr = "2001:db8:0:1::1".parse().unwrap();
+        assert!(throttle.try_attempt("admin", address).is_some());
+    }
+
+    #[test]
+    fn throttle_bounds_concurrent_verifications() {
+        let throttle = LoginThrottle::default();
+        let permits: Vec<_> = (0..MAX_CONCURRENT_VERIFICATIONS)
+            .map(|i| {
+                let address = IpAddr::from([192, 0, 2, i as u8]);
+                throttle.try_attempt("admin", address).unwrap()
+            })
+            .collect();
+        let address: IpAddr = "198.51.100.1".parse().unwrap();
+        assert!(throttle.try_attempt("admin", address).is_none());
+        drop(permits);
+        assert!(throttle.try_attempt("admin", address).is_some());
+    }
+}
@@ -1,120 +1,150 @@
-use std::collections::HashMap;
-use std::env;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
-use axum::{routing::get_service, serve, Router};
+use axum::Router;
 use clap::Parser;
-use jmespath::compile;
-use machine_launcher::drivers::ipmi::IpmiDriver;
-use once_cell::sync::Lazy;
-use openidconnect::{
-    core::CoreProviderMetadata, ClientId, ClientSecret, IssuerUrl, RedirectUrl, TokenUrl,
-};
-use regex::Regex;
-use tokio::net::TcpListener;
-use tower_http::{services::ServeDir, trace::TraceLayer};
-use url::Url;
+use tower_http::trace::TraceLayer;
 
 use machine_launcher::{
-    cmd::{Args, Config, DriverType},
-    drivers::{debug::DebugDriver, traits::PowerManagerTrait, wake_on_lan::WakeOnLanDriver},
-    AppState, OidcClient,
+    api_tokens,
+    cmd::{check_config, Args, Command, Config},
+    ctl,
+    local_auth::{self, LoginThrottle},
+    oidc::{self, OidcProviders},
+    pending_logins::{spawn_cleanup, PendingLogins},
+    rate_limit::{self, RateLimiter},
+    reload::{spawn_watcher, ReloadableState},
+    sessions::{self, RenewLocks},
+    shutdown::Shutdown,
+    store::Store,
+    AppState,
 };
 
-const COMPILED_FILES_PATH: &str = "../frontend/dist/";
-const STATIC_FILES_PATH: &str = "../frontend/public/";
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Parse arguments
+    let args = Args::parse();
+
     // Setup logger
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "info,tower_http=debug,axum::rejection=trace");
     }
-    tracing_subscriber::fmt::init();
-
-    // Parse arguments
-    let args = Args::parse();
+    if args.command.is_some() {
+        // MEMO: not to mix logs with outputs of subcommands
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
 
-    // Parse config file
-    let config_str =
-        std::fs::read_to_string(args.config).expect("args.config should be readable as file");
-    let config_str_templated =
-        substitute_env_variables(&config_str).expect("config should be templated by envsubst");
-    let config: Config = toml::from_str(&config_str_templated).expect("config should be parsed");
-
-    // Drivers
-    let mut drivers = HashMap::<String, Arc<dyn PowerManagerTrait>>::new();
-    for driver_conf in config.drivers {
-        match driver_conf {
-            DriverType::Debug(c) => {
-                drivers.insert(c.name.clone(), Arc::new(DebugDriver::new(c.name.clone())))
+    match args.command {
+        Some(Command::CheckConfig { config }) => {
+            let problems = check_config(&config);
+            if problems.is_empty() {
+                println!("{}: OK", config);
+                return Ok(());
+            }
+            for problem in problems {
+                eprintln!("{}", problem);
+            }
+            std::process::exit(1);
+        }
+        Some(Command::HashPassword) => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            if password.is_empty() {
+                eprintln!("password must not be empty");
+                std::process::exit(1);
+            }
+            match local_auth::hash_password(password) {
+                Ok(hash) => println!("{}", hash),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Ctl {
+            config,
+            output,
+            command,
+        }) => {
+            if let Err(e) = ctl::run(&config, output, command) {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
-            DriverType::Ipmi(c) => drivers.insert(
-                c.name.clone(),
-                Arc::new(
-                    IpmiDriver::new(c.name.clone(), c.server_addr, c.username, c.password)
-                        .expect("IpmiDriver cloud not be initialized"),
-                ),
-            ),
-            DriverType::Wol(c) => drivers.insert(
-                c.name.clone(),
-                Arc::new(
-                    WakeOnLanDriver::new(c.name.clone(), c.mac_addr, c.ip_addr)
-                        .expect("WakeOnLanDriver cloud not be initialized"),
-                ),
-            ),
-        };
+            return Ok(());
+        }
+        None => {}
     }
+    let config_path = args.config.expect("--config should be specified");
 
-    // OIDC Client
-    let http_client = &reqwest::Client::new();
-    let oidc_provider_metadata = CoreProviderMetadata::discover_async(
-        IssuerUrl::new(config.oidc.provider_url)?,
-        http_client,
-    )
-    .await
-    .expect("Failed to discover provider metadata");
-    let oidc_client = OidcClient::from_provider_metadata(
-        oidc_provider_metadata.clone(),
-        ClientId::new(config.oidc.client_id),
-        Some(ClientSecret::new(config.oidc.client_secret)),
-    )
-    .set_redirect_uri(RedirectUrl::new(
-        Url::parse(&config.url)
-            .unwrap()
-            .join("/auth/callback")
-            .unwrap()
-            .to_string(),
-    )?)
-    // TODO: use set_revocation_url
-    //.set_revocation_url(RevocationUrl::new(
-    //    oidc_provider_metadata
-    //        .additional_metadata()
-    //        .revocation_endpoint
-    //        .clone(),
-    //)?)
-    .set_token_uri(TokenUrl::new(
-        oidc_provider_metadata
-            .clone()
-            .token_endpoint()
-            .unwrap()
-            .to_string(),
-    )?);
-    let pkce_verifiers = Mutex::new(HashMap::new());
-
-    // Authorization based on ID Token
-    let role_attribute_path_expr = compile(&config.oidc.role_attribute_path)
-        .expect("args.role_attribute_path should be JMESPath format.");
+    // Parse config file
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let url = config.url.clone();
+    let server_config = config.server.clone();
+
+    // Storage
+    let store = match Store::open(&config.storage) {
+        Ok(store) => Arc::new(store),
+        Err(e) => {
+            tracing::error!("failed to open storage: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // OIDC Clients
+    // MEMO: start even if providers are unreachable, to power on machines by local accounts
+    // (e.g. the router is one of the machines). They are retried in the background.
+    let oidc_providers = match OidcProviders::new(config.oidc_configs().cloned().collect(), url) {
+        Ok(oidc_providers) => oidc_providers,
+        Err(e) => {
+            tracing::error!("failed to build HTTP client: {}", e);
+            std::process::exit(1);
+        }
+    };
+    oidc_providers.discover_all().await;
+
+    // Drivers & Authorization based on ID Token
+    let reloadable =
+        ReloadableState::build(config, None).expect("config should be built successfully");
 
     // AppState
     let app_state = Arc::new(AppState {
-        drivers,
-        oidc_client,
-        role_attribute_path_expr,
-        pkce_verifiers,
+        reloadable: RwLock::new(Arc::new(reloadable)),
+        oidc_providers,
+        pending_logins: PendingLogins::default(),
+        login_limiter: RateLimiter::new(
+            rate_limit::MAX_LOGIN_STARTS,
+            rate_limit::LOGIN_START_WINDOW,
+        ),
+        local_login_throttle: LoginThrottle::default(),
+        renew_locks: RenewLocks::default(),
+        shutdown: Shutdown::default(),
+        store,
     });
 
+    // Reload config on file changes or SIGHUP
+    spawn_watcher(app_state.clone(), PathBuf::from(&config_path))?;
+
+    // Remove expired logins and sessions periodically
+    spawn_cleanup(app_state.clone());
+    sessions::spawn_cleanup(app_state.clone());
+    api_tokens::spawn_cleanup(app_state.clone());
+    rate_limit::spawn_cleanup(app_state.clone());
+
+    // Discover unreachable OIDC providers and refresh signing keys periodically
+    oidc::spawn_refresher(app_state.clone());
+
     // Routing
     let app = Router::new()
         .nest(
@@ -122,35 +152,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             machine_launcher::handlers_app::routes(app_state.clone()),
         )
         .nest("/auth", machine_launcher::handlers_oauth::routes())
-        .nest_service("/public", get_service(ServeDir::new(STATIC_FILES_PATH)))
-        .fallback_service(get_service(ServeDir::new(COMPILED_FILES_PATH)))
-        .with_state(app_state)
+        .merge(machine_launcher::assets::routes(
+            server_config.frontend_dir.as_deref(),
+        ))
+        .with_state(app_state.clone())
         .layer(TraceLayer::new_for_http());
 
     // Serve
-    let tcp_listener =
-        if let Some(std_listener) = listenfd::ListenFd::from_env().take_tcp_listener(0)? {
-            TcpListener::from_std(std_listener)?
-        } else {
-            TcpListener::bind(&SocketAddr::from(([0, 0, 0, 0], 8080))).await?
-        };
-    serve(tcp_listener, app).await?;
+    machine_launcher::server::serve(&server_config, app, &app_state.shutdown).await?;
 
     Ok(())
 }
-
-static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\{(\w+)\}").unwrap());
-
-fn substitute_env_variables(input: &str) -> Result<String, Box<dyn std::error::Error>> {
-    if envsubst::is_templated(input) {
-        let mut context = std::collections::HashMap::new();
-        for (_, [cap]) in RE.captures_iter(input).map(|c| c.extract()) {
-            let val = env::var(cap)?;
-            context.insert(cap.to_string(), val);
-        }
-        let res = envsubst::substitute(input, &context)?;
-        Ok(res)
-    } else {
-        Ok(input.to_string())
-    }
-}
@@ -3,7 +3,8 @@ use std::{collections::HashSet, str::FromStr};
 
 use axum::RequestExt;
 use axum::{
-    extract::{Request, State},
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderMap},
     middleware::Next,
     response::IntoResponse,
 };
@@ -12,16 +13,22 @@ use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
-use jmespath::Variable;
 use openidconnect::{
     core::{CoreIdToken, CoreIdTokenClaims},
-    Nonce, NonceVerifier,
+    Nonce,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::api_tokens::{find_api_token, is_api_token, TokenScope};
+use crate::cmd::LOCAL_PROVIDER_NAME;
+use crate::local_auth;
+use crate::policy::Authorizer;
+use crate::sessions::{csrf_token, find_session};
 use crate::{AppState, Error};
 use machine_launcher_utils::{all_claims_from_jwt, COOKIE_KEY};
 
+const CSRF_HEADER: &str = "x-csrf-token";
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub status: &'static str,
@@ -33,12 +40,16 @@ pub struct Claims {
     _permissions: Option<HashSet<String>>,
 }
 
-// TODO: use real nonce from input
-struct DummyNonceVerifier {}
-impl NonceVerifier for &DummyNonceVerifier {
-    fn verify(self, _: Option<&Nonce>) -> Result<(), String> {
-        Ok(())
-    }
+/// The user authenticated by `auth_middleware`, available as a request extension in handlers.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    // Name of the OIDC provider which authenticated the user
+    pub provider: String,
+    pub claims: serde_json::Value,
+    // Scope of the API token if the request is authenticated by it
+    pub scope: Option<TokenScope>,
+    // CSRF token of the session if the request is authenticated by the session cookie
+    pub csrf_token: Option<String>,
 }
 
 pub async fn auth_middleware(
@@ -47,69 +58,285 @@ pub async fn auth_middleware(
     mut req: Request,
     next: Next,
 ) -> Result<impl IntoResponse, Error> {
-    // get credential
-    let mut id_token_opt = cookie_jar
-        .get(COOKIE_KEY)
-        .map(|cookie| cookie.value().to_string());
-    if id_token_opt.is_none() {
-        id_token_opt = Some(
-            req.extract_parts::<TypedHeader<Authorization<Bearer>>>()
+    // get credential: the session cookie of browsers, or the ID token or the API token
+    // in Authorization header
+    let mut user = match cookie_jar.get(COOKIE_KEY) {
+        Some(cookie) => {
+            let session = find_session(&state, cookie.value())
+                .await?
+                .ok_or_else(|| Error::Unauthorized("Session expired, please login again".into()))?;
+            let csrf_token = csrf_token(cookie.value());
+            if !req.method().is_safe() {
+                verify_csrf_token(&state, req.headers(), &csrf_token)?;
+            }
+            AuthenticatedUser {
+                provider: session.provider,
+                claims: session.claims,
+                scope: None,
+                csrf_token: Some(csrf_token),
+            }
+        }
+        None => {
+            let token = req
+                .extract_parts::<TypedHeader<Authorization<Bearer>>>()
                 .await
                 .map_err(|_| {
                     Error::Unauthorized("You are not logged in, please provide token".into())
                 })?
                 .token()
-                .to_string(),
-        );
+                .to_string();
+            if is_api_token(&token) {
+                let api_token = find_api_token(&state, &token)
+                    .await?
+                    .ok_or_else(|| Error::Unauthorized("API token is invalid or expired".into()))?;
+                AuthenticatedUser {
+                    scope: Some(TokenScope::from(&api_token)),
+                    provider: api_token.provider,
+                    claims: api_token.claims,
+                    csrf_token: None,
+                }
+            } else {
+                let (provider, claims) = claims_from_id_token(&state, &token).await?;
+                AuthenticatedUser {
+                    provider,
+                    claims,
+                    scope: None,
+                    csrf_token: None,
+                }
+            }
+        }
+    };
+
+    // MEMO: local accounts are defined in the config, so sessions and API tokens of the ones
+    // removed or disabled are refused, and roles changed there are applied immediately.
+    if user.provider == LOCAL_PROVIDER_NAME {
+        let username = user
+            .claims
+            .get("sub")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        user.claims =
+            local_auth::current_claims(state.reloadable().config.local_auth.as_ref(), username)
+                .ok_or_else(|| {
+                    Error::Unauthorized("Local account is removed or disabled".into())
+                })?;
     }
-    let id_token_str = id_token_opt.unwrap();
-
-    //cookie_jar
-    //    .get(COOKIE_KEY)
-    //    .map(|cookie| cookie.value().to_string())
-    //    .or_else(|| {
-    //        req.extract_parts::<TypedHeader<Authorization<Bearer>>>()
-    //            .await
-    //            .ok_or_else(|| {
-    //                Error::Unauthorized("You are not logged in, please provide token".into())
-    //            })?
-    //    })
-    //    .ok_or_else(|| Error::Unauthorized("You are not logged in, please provide token".into()))?;
-    let id_token: CoreIdToken = openidconnect::IdToken::from_str(&id_token_str)
-        .map_err(|e| Error::Forbidden(format!("Provided token is not IdToken: {:?}", e).into()))?;
-    let _: &CoreIdTokenClaims = id_token
-        .claims(
-            &state.oidc_client.id_token_verifier(),
-            &DummyNonceVerifier {},
-        )
-        .map_err(|e| Error::Forbidden(format!("Provided token is invalid: {:?}", e).into()))?;
 
+    // MEMO: users allowed nothing are rejected here, and the machines and actions allowed are
+    // checked by handlers, which know the machine of the request.
+    let path = match req.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_string(),
+        None => req.uri().path().to_string(),
+    };
+    if !Authorizer::new(state.reloadable(), &user, req.method(), &path).has_role() {
+        return Err(Error::Forbidden("no role found".into()));
+    }
+    req.extensions_mut().insert(user);
+    Ok(next.run(req).await)
+}
+
+// MEMO: browsers attach the session cookie to requests from other sites, and SameSite does not
+// stop the ones from apps on sibling subdomains, which are the same site. So requests changing
+// the state by the cookie must come from this origin and have the CSRF token of the session,
+// which only pages of this origin can get from `/api/me`. Requests by Bearer tokens are exempt,
+// because browsers never attach them automatically.
+fn verify_csrf_token(state: &AppState, headers: &HeaderMap, expected: &str) -> Result<(), Error> {
+    verify_same_origin(state, headers)?;
+    let token = headers
+        .get(CSRF_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    check_csrf_token(token, expected)
+}
+
+/// Reject `token` unless it is the CSRF token `expected` of the session.
+pub fn check_csrf_token(token: &str, expected: &str) -> Result<(), Error> {
+    if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+        return Err(Error::Forbidden("CSRF token is missing or invalid".into()));
+    }
+    Ok(())
+}
+
+/// Reject requests whose Origin (or Referer if Origin is not sent) is not the origin of `url`.
+pub fn verify_same_origin(state: &AppState, headers: &HeaderMap) -> Result<(), Error> {
+    check_origin(&state.reloadable().config.url, headers)
+}
+
+fn check_origin(url: &str, headers: &HeaderMap) -> Result<(), Error> {
+    let expected = url::Url::parse(url)
+        .map_err(|e| Error::InternalServerError(format!("invalid url: {}", e).into()))?
+        .origin();
+    let origin = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| url::Url::parse(v).ok())
+        .map(|url| url.origin());
+    match origin {
+        Some(origin) if origin == expected => Ok(()),
+        Some(_) => Err(Error::Forbidden(
+            "requests from other origins are rejected".into(),
+        )),
+        None => Err(Error::Forbidden(
+            "Origin or Referer header is required".into(),
+        )),
+    }
+}
+
+// Compare in the time independent of where they differ, not to leak the token by timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Verify the ID token passed by API clients by the provider of its issuer,
+// and return the name of the provider and all claims of the token.
+// Bearer ID tokens have no replay protection: any unexpired ID token issued to this client is
+// accepted as many times as it is sent, so a leaked one works until it expires.
+async fn claims_from_id_token(
+    state: &AppState,
+    id_token_str: &str,
+) -> Result<(String, serde_json::Value), Error> {
+    let id_token: CoreIdToken = openidconnect::IdToken::from_str(id_token_str)
+        .map_err(|e| Error::Forbidden(format!("Provided token is not IdToken: {:?}", e).into()))?;
     // MEMO: CoreIdToken cannot contain custom claims decided dynamically,
-    // so get all claims as HashMap after JWT validations.
+    // so get all claims as HashMap, which are trusted only after JWT validations.
     let all_claims =
-        all_claims_from_jwt(&id_token_str).map_err(|e| Error::Forbidden(e.to_string().into()))?;
-
-    // And, authorized based on JMESPath
-    authorize_based_jmespath(all_claims, state)?;
+        all_claims_from_jwt(id_token_str).map_err(|e| Error::Forbidden(e.to_string().into()))?;
+    let issuer = all_claims
+        .get("iss")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let provider = state.oidc_providers.by_issuer(issuer).ok_or_else(|| {
+        Error::Forbidden(format!("Provided token is issued by unknown issuer {:?}", issuer).into())
+    })?;
+    // MEMO: ID tokens in Authorization header are obtained outside of the login flow
+    // of this server, so there is no nonce to compare with, and the nonce is not checked.
+    let nonce_verifier = |_: Option<&Nonce>| Ok(());
+    let _: CoreIdTokenClaims = state
+        .oidc_providers
+        .verify_id_token(&provider, &id_token, nonce_verifier)
+        .await
+        .map_err(|e| Error::Forbidden(format!("Provided token is invalid: {:?}", e).into()))?;
 
-    Ok(next.run(req).await)
+    Ok((
+        provider.name.clone(),
+        serde_json::Value::Object(all_claims.into_iter().collect()),
+    ))
 }
 
-fn authorize_based_jmespath<T: Serialize>(claims: T, state: Arc<AppState>) -> Result<(), Error> {
-    let jmespath_data = Variable::from_serializable(claims)
-        .map_err(|e| Error::InternalServerError(format!("invalid claims: {:?}", e).into()))?;
-    if !state
-        .role_attribute_path_expr
-        .search(jmespath_data)
-        // JMESPath evaluation was not matched
-        .map_err(|_| Error::Forbidden("no role found".into()))?
-        .as_boolean()
-        // result of JMESPath is not boolean
-        .ok_or_else(|| Error::Forbidden("no role found".into()))?
-    {
-        // result of JMESPath is false
-        Err(Error::Forbidden("no role found".into()))
-    } else {
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const URL: &str = "https://launcher.example.com";
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name.clone(), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn check_origin_accepts_same_origin() {
+        assert!(check_origin(URL, &headers(&[(header::ORIGIN, URL)])).is_ok());
+        assert!(check_origin(
+            URL,
+            &headers(&[(header::REFERER, "https://launcher.example.com/#/tokens")])
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn check_origin_rejects_other_origins() {
+        for origin in [
+            "https://evil.example.com",
+            "https://sub.launcher.example.com",
+            "http://launcher.example.com",
+            "https://launcher.example.com:8443",
+            "null",
+        ] {
+            let headers = headers(&[(header::ORIGIN, origin)]);
+            assert!(check_origin(URL, &headers).is_err(), "{}", origin);
+        }
+        // Origin takes precedence over Referer
+        let headers = headers(&[
+            (header::ORIGIN, "https://evil.example.com"),
+            (header::REFERER, "https://launcher.example.com/"),
+        ]);
+        assert!(check_origin(URL, &headers).is_err());
+    }
+
+    #[test]
+    fn check_origin_requires_origin_or_referer() {
+        assert!(check_origin(URL, &HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn check_csrf_token_compares_whole_token() {
+        let expected = csrf_token("session");
+        assert!(check_csrf_token(&expected, &expected).is_ok());
+        assert!(check_csrf_token("", &expected).is_err());
+        assert!(check_csrf_token(&expected[..expected.len() - 1], &expected).is_err());
+        assert!(check_csrf_token(&csrf_token("other"), &expected).is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn auth_middleware_rejects_users_without_roles() {
+        use axum::http::StatusCode;
+        use tower::ServiceExt;
+
+        let hash = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaGhhc2hoYXNo";
+        let config = crate::cmd::Config::parse(&format!(
+            r#"
+url = "{}"
+drivers = []
+[[local_auth.users]]
+username = "admin"
+password_hash = "{}"
+roles = ["admin"]
+[[local_auth.users]]
+username = "guest"
+password_hash = "{}"
+"#,
+            URL, hash, hash
+        ))
+        .unwrap();
+        let state = AppState::for_tests(config);
+        let app = axum::Router::new()
+            .nest("/api", crate::handlers_app::routes(state.clone()))
+            .with_state(state.clone());
+
+        for (username, status) in [("admin", StatusCode::OK), ("guest", StatusCode::FORBIDDEN)] {
+            let session_token = crate::sessions::create_session(
+                &state,
+                LOCAL_PROVIDER_NAME,
+                serde_json::json!({"sub": username}),
+                String::new(),
+                None,
+                i64::MAX,
+            )
+            .await
+            .unwrap();
+            for path in ["/api/me", "/api/servers"] {
+                let req = Request::builder()
+                    .uri(path)
+                    .header(header::COOKIE, format!("{}={}", COOKIE_KEY, session_token))
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let res = app.clone().oneshot(req).await.unwrap();
+                assert_eq!(res.status(), status, "{} {}", username, path);
+            }
+        }
     }
 }
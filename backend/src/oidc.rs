@@ -0,0 +1,458 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use openidconnect::core::{CoreIdToken, CoreIdTokenClaims};
+use openidconnect::{
+    ClaimsVerificationError, ClientId, ClientSecret, EndSessionUrl, EndpointMaybeSet, EndpointSet,
+    IssuerUrl, NonceVerifier, RedirectUrl, RevocationUrl, SignatureVerificationError, TokenUrl,
+};
+use url::Url;
+
+use crate::cmd::{BoxError, OidcConfig};
+use crate::{AppState, OidcClient, OidcProviderMetadata};
+
+// Re-discover providers to pick up rotated signing keys (JWKS) and metadata changes
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Retry the discovery of providers which have not been reachable since startup, and check
+// whether discovered ones are still reachable (e.g. to enable local accounts as the fallback)
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+// Timeouts of requests to providers, not to hang when a provider is unreachable
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Minimum interval of discoveries of the same provider, not to flood it by tokens of unknown keys
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An OIDC provider whose metadata is discovered on startup.
+pub struct OidcProvider {
+    pub name: String,
+    pub issuer: IssuerUrl,
+    pub client: OidcClient<EndpointSet, EndpointMaybeSet>,
+    pub offline_access: bool, // Request refresh tokens, if the OIDC provider supports
+    pub end_session_url: Option<EndSessionUrl>, // Redirect to on logout, if the OIDC provider supports
+    pub revocation_url: Option<RevocationUrl>, // Revoke tokens on logout, if the OIDC provider supports
+}
+impl OidcProvider {
+    /// Discover the provider of `config`, and build the client which redirects back to `url`.
+    pub async fn discover(
+        config: &OidcConfig,
+        url: &str,
+        http_client: &reqwest::Client,
+    ) -> Result<Self, BoxError> {
+        let metadata = OidcProviderMetadata::discover_async(
+            IssuerUrl::new(config.provider_url.clone())?,
+            http_client,
+        )
+        .await?;
+        let offline_access = metadata
+            .scopes_supported()
+            .is_some_and(|scopes| scopes.iter().any(|s| s.as_str() == "offline_access"));
+        let end_session_url = metadata.additional_metadata().end_session_endpoint.clone();
+        let revocation_url = metadata
+            .additional_metadata()
+            .additional_metadata
+            .revocation_endpoint
+            .clone();
+        let token_url = metadata
+            .token_endpoint()
+            .ok_or("token_endpoint is not found in the provider metadata")?
+            .to_string();
+        // MEMO: the callback is shared by all providers, which are told apart by the state.
+        let redirect_url = Url::parse(url)?.join("/auth/callback")?.to_string();
+        let client = OidcClient::from_provider_metadata(
+            metadata.clone(),
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.client_secret.clone())),
+        )
+        .set_redirect_uri(RedirectUrl::new(redirect_url)?)
+        .set_token_uri(TokenUrl::new(token_url)?);
+
+        Ok(OidcProvider {
+            name: config.name.clone(),
+            issuer: metadata.issuer().clone(),
+            client,
+            offline_access,
+            end_session_url,
+            revocation_url,
+        })
+    }
+}
+
+/// OIDC providers of the config file. Providers unreachable on startup are discovered later in
+/// the background, and discovered ones are refreshed periodically and on unknown signing keys.
+/// Changes of the config file are not applied until restart.
+pub struct OidcProviders {
+    configs: Vec<OidcConfig>,
+    url: String,
+    http_client: reqwest::Client,
+    providers: RwLock<HashMap<String, Arc<OidcProvider>>>,
+    // When the last discovery of each provider finished, and whether it succeeded
+    last_tried: Mutex<HashMap<String, (Instant, bool)>>,
+    // Held while each provider is discovered, so that concurrent refreshes wait for it
+    refreshing: HashMap<String, tokio::sync::Mutex<()>>,
+    // Providers which answered the last discovery or reachability check
+    reachable: RwLock<HashSet<String>>,
+}
+impl OidcProviders {
+    pub fn new(configs: Vec<OidcConfig>, url: String) -> Result<Self, BoxError> {
+        // MEMO: redirects are not followed, not to be led to other hosts by responses (SSRF).
+        let http_client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let refreshing = configs
+            .iter()
+            .map(|c| (c.name.clone(), tokio::sync::Mutex::default()))
+            .collect();
+        Ok(OidcProviders {
+            configs,
+            url,
+            http_client,
+            providers: RwLock::default(),
+            last_tried: Mutex::default(),
+            refreshing,
+            reachable: RwLock::default(),
+        })
+    }
+
+    /// HTTP client for requests to providers (discovery, token exchange and revocation).
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    /// Discover all providers. Failures are logged and retried by `spawn_refresher`.
+    pub async fn discover_all(&self) {
+        let names: Vec<String> = self.configs.iter().map(|c| c.name.clone()).collect();
+        for name in names {
+            self.refresh(&name, Duration::ZERO).await;
+        }
+    }
+
+    /// Discovered providers in the order of the config file.
+    pub fn all(&self) -> Vec<Arc<OidcProvider>> {
+        let providers = self.providers.read().unwrap();
+        self.configs
+            .iter()
+            .filter_map(|c| providers.get(&c.name).cloned())
+            .collect()
+    }
+
+    /// Whether any provider answered the last discovery or reachability check.
+    /// MEMO: discovered providers are kept while unreachable, to verify tokens they issued.
+    pub fn any_reachable(&self) -> bool {
+        !self.reachable.read().unwrap().is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<OidcProvider>> {
+        self.providers.read().unwrap().get(name).cloned()
+    }
+
+    /// Find the provider which issues tokens of `issuer` (the `iss` claim).
+    pub fn by_issuer(&self, issuer: &str) -> Option<Arc<OidcProvider>> {
+        self.providers
+            .read()
+            .unwrap()
+            .values()
+            .find(|p| p.issuer.as_str() == issuer)
+            .cloned()
+    }
+
+    /// Verify `id_token` by `provider`. If it is signed by an unknown key, the provider may have
+    /// rotated its keys, so verify it again after refreshing the keys (at most once per
+    /// `MIN_REFRESH_INTERVAL`).
+    pub async fn verify_id_token<N: NonceVerifier + Copy>(
+        &self,
+        provider: &OidcProvider,
+        id_token: &CoreIdToken,
+        nonce_verifier: N,
+    ) -> Result<CoreIdTokenClaims, ClaimsVerificationError> {
+        match id_token.claims(&provider.client.id_token_verifier(), nonce_verifier) {
+            Err(ClaimsVerificationError::SignatureVerification(
+                SignatureVerificationError::NoMatchingKey,
+            )) if self.refresh(&provider.name, MIN_REFRESH_INTERVAL).await => {
+                let provider = self.get(&provider.name).ok_or_else(|| {
+                    ClaimsVerificationError::Other("OIDC provider is not found".to_string())
+                })?;
+                let verifier = provider.client.id_token_verifier();
+                id_token.claims(&verifier, nonce_verifier).cloned()
+            }
+            result => result.cloned(),
+        }
+    }
+
+    // Discover the provider of `name` again unless it was tried within `min_interval`,
+    // and return whether it is discovered. If it is being discovered, wait for the result.
+    async fn refresh(&self, name: &str, min_interval: Duration) -> bool {
+        let (Some(config), Some(refreshing)) = (
+            self.configs.iter().find(|c| c.name == name),
+            self.refreshing.get(name),
+        ) else {
+            return false;
+        };
+        let requested_at = Instant::now();
+        let _refreshing = refreshing.lock().await;
+        if let Some((tried_at, discovered)) = self.last_tried.lock().unwrap().get(name) {
+            // MEMO: finished while waiting, so the keys are as new as this refresh would get.
+            if *tried_at >= requested_at {
+                return *discovered;
+            }
+            if requested_at.duration_since(*tried_at) < min_interval {
+                return false;
+            }
+        }
+        let discovered = self.discover(config).await;
+        self.last_tried
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (Instant::now(), discovered));
+        discovered
+    }
+
+    async fn discover(&self, config: &OidcConfig) -> bool {
+        let name = &config.name;
+        match OidcProvider::discover(config, &self.url, &self.http_client).await {
+            Ok(provider) => {
+                let previous = self
+                    .providers
+                    .write()
+                    .unwrap()
+                    .insert(name.to_string(), Arc::new(provider));
+                match previous {
+                    Some(_) => tracing::debug!("OIDC provider {:?} is refreshed", name),
+                    None => tracing::info!("OIDC provider {:?} is discovered", name),
+                }
+                self.set_reachable(name, true);
+                true
+            }
+            Err(e) => {
+                tracing::warn!("failed to discover OIDC provider {:?}: {}", name, e);
+                self.set_reachable(name, false);
+                false
+            }
+        }
+    }
+
+    // Check whether the discovered provider of `name` still answers its metadata.
+    async fn check_reachable(&self, name: &str) {
+        let Some(config) = self.configs.iter().find(|c| c.name == name) else {
+            return;
+        };
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            config.provider_url.trim_end_matches('/')
+        );
+        let reachable = match self.http_client.get(&url).send().await {
+            Ok(resp) if resp.status().is_success() => true,
+            Ok(resp) => {
+                tracing::debug!("OIDC provider {:?} answered {}", name, resp.status());
+                false
+            }
+            Err(e) => {
+                tracing::debug!("OIDC provider {:?} is unreachable: {}", name, e);
+                false
+            }
+        };
+        self.set_reachable(name, reachable);
+    }
+
+    fn set_reachable(&self, name: &str, reachable: bool) {
+        let mut names = self.reachable.write().unwrap();
+        let changed = if reachable {
+            names.insert(name.to_string())
+        } else {
+            names.remove(name)
+        };
+        if changed && reachable {
+            tracing::info!("OIDC provider {:?} is reachable", name);
+        } else if changed {
+            tracing::warn!("OIDC provider {:?} is unreachable", name);
+        }
+    }
+
+    // Time until the provider of `name` should be discovered again.
+    fn next_refresh(&self, name: &str) -> Duration {
+        let interval = if self.providers.read().unwrap().contains_key(name) {
+            REFRESH_INTERVAL
+        } else {
+            RETRY_INTERVAL
+        };
+        self.last_tried
+            .lock()
+            .unwrap()
+            .get(name)
+            .map_or(Duration::ZERO, |(t, _)| {
+                interval.saturating_sub(t.elapsed())
+            })
+    }
+}
+
+/// Retry the discovery of unreachable providers and refresh discovered ones periodically.
+/// Between refreshes, discovered providers are checked to be reachable every `RETRY_INTERVAL`.
+pub fn spawn_refresher(app_state: Arc<AppState>) {
+    let shutdown = app_state.shutdown.clone();
+    app_state.shutdown.clone().spawn(async move {
+        let providers = &app_state.oidc_providers;
+        let mut interval = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            for config in &providers.configs {
+                if providers.next_refresh(&config.name).is_zero() {
+                    providers.refresh(&config.name, Duration::ZERO).await;
+                } else if providers.get(&config.name).is_some() {
+                    providers.check_reachable(&config.name).await;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json};
+    use base64::prelude::*;
+    use serde_json::json;
+
+    // OIDC provider answering the discovery with no signing keys
+    struct MockProvider {
+        issuer: String,
+        discoveries: AtomicUsize,
+        down: AtomicBool,
+    }
+
+    async fn start_provider() -> Arc<MockProvider> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let mock = Arc::new(MockProvider {
+            issuer,
+            discoveries: AtomicUsize::new(0),
+            down: AtomicBool::new(false),
+        });
+        let app = axum::Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(|State(mock): State<Arc<MockProvider>>| async move {
+                    mock.discoveries.fetch_add(1, Ordering::SeqCst);
+                    // long enough for concurrent refreshes to overlap
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    if mock.down.load(Ordering::SeqCst) {
+                        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+                    }
+                    Json(json!({
+                        "issuer": mock.issuer,
+                        "authorization_endpoint": format!("{}/authorize", mock.issuer),
+                        "token_endpoint": format!("{}/token", mock.issuer),
+                        "jwks_uri": format!("{}/jwks", mock.issuer),
+                        "response_types_supported": ["code"],
+                        "subject_types_supported": ["public"],
+                        "id_token_signing_alg_values_supported": ["RS256"],
+                    }))
+                    .into_response()
+                }),
+            )
+            .route("/jwks", get(|| async { Json(json!({"keys": []})) }))
+            .with_state(mock.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        mock
+    }
+
+    fn providers(mock: &MockProvider) -> OidcProviders {
+        let config: OidcConfig = toml::from_str(&format!(
+            r#"
+provider_url = "{}"
+client_id = "cid"
+client_secret = "csecret"
+role_attribute_path = "`true`"
+"#,
+            mock.issuer
+        ))
+        .unwrap();
+        OidcProviders::new(vec![config], "https://launcher.example.com".to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn refresh_is_limited_by_min_interval() {
+        let mock = start_provider().await;
+        let providers = providers(&mock);
+        assert!(providers.refresh("default", MIN_REFRESH_INTERVAL).await);
+        assert!(!providers.refresh("default", MIN_REFRESH_INTERVAL).await);
+        assert_eq!(mock.discoveries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_refreshes_wait_for_the_one_in_flight() {
+        let mock = start_provider().await;
+        let providers = providers(&mock);
+        let (a, b) = tokio::join!(
+            providers.refresh("default", MIN_REFRESH_INTERVAL),
+            providers.refresh("default", MIN_REFRESH_INTERVAL),
+        );
+        assert!(a && b);
+        assert_eq!(mock.discoveries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_keys_refresh_the_provider_at_most_once_per_interval() {
+        let mock = start_provider().await;
+        let providers = providers(&mock);
+        providers.discover_all().await;
+        let provider = providers.get("default").unwrap();
+        // as if discovered long ago
+        let long_ago = Instant::now().checked_sub(MIN_REFRESH_INTERVAL).unwrap();
+        providers
+            .last_tried
+            .lock()
+            .unwrap()
+            .insert("default".to_string(), (long_ago, true));
+        let encode = |v: serde_json::Value| BASE64_URL_SAFE_NO_PAD.encode(v.to_string());
+        let id_token: CoreIdToken = format!(
+            "{}.{}.{}",
+            encode(json!({"alg": "RS256", "kid": "rotated"})),
+            encode(json!({
+                "iss": mock.issuer,
+                "aud": "cid",
+                "sub": "user1",
+                "exp": chrono::Utc::now().timestamp() + 60,
+                "iat": chrono::Utc::now().timestamp(),
+            })),
+            BASE64_URL_SAFE_NO_PAD.encode("signature"),
+        )
+        .parse()
+        .unwrap();
+        let no_nonce = |_: Option<&openidconnect::Nonce>| Ok(());
+
+        // discovered on startup, and only once more for the unknown key
+        for _ in 0..3 {
+            let result = providers
+                .verify_id_token(&provider, &id_token, no_nonce)
+                .await;
+            assert!(result.is_err());
+        }
+        assert_eq!(mock.discoveries.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unreachable_providers_are_retried() {
+        let mock = start_provider().await;
+        mock.down.store(true, Ordering::SeqCst);
+        let providers = providers(&mock);
+        providers.discover_all().await;
+        assert!(providers.get("default").is_none());
+        assert!(!providers.any_reachable());
+        let next = providers.next_refresh("default");
+        assert!(!next.is_zero() && next <= RETRY_INTERVAL);
+
+        // `spawn_refresher` discovers it again when `next_refresh` is zero
+        mock.down.store(false, Ordering::SeqCst);
+        assert!(providers.refresh("default", Duration::ZERO).await);
+        assert!(providers.get("default").is_some());
+        assert!(providers.any_reachable());
+        assert!(providers.next_refresh("default") > RETRY_INTERVAL);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use openidconnect::{Nonce, PkceCodeVerifier};
+
+use crate::AppState;
+
+// Time for users to complete the login on the OIDC provider
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// Upper bound of logins in progress, not to grow memory by requests to `/auth/login` without limit
+const MAX_PENDING_LOGINS: usize = 10_000;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Secrets of the login flow kept from `/auth/login` until `/auth/callback`, keyed by the state.
+pub struct PendingLogin {
+    // Name of the OIDC provider the login is started with
+    pub provider: String,
+    pub pkce_verifier: PkceCodeVerifier,
+    pub nonce: Nonce,
+    // Path to redirect to after login, validated on `/auth/login`
+    pub return_to: String,
+}
+
+/// Logins in progress. They expire after `LOGIN_TIMEOUT`, and the oldest one is dropped
+/// when `MAX_PENDING_LOGINS` is exceeded.
+// MEMO: kept in memory instead of the store, because they live only for minutes and a restart
+// costs users only a retry of the login, while the PKCE verifiers would be secrets on disk.
+#[derive(Default)]
+pub struct PendingLogins {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    logins: HashMap<String, (Instant, PendingLogin)>,
+    // States in the order of creation, which is also the order of expiry.
+    // MEMO: states already taken are skipped on eviction, and removed when they are the majority.
+    order: VecDeque<(Instant, String)>,
+}
+
+impl Inner {
+    // Remove expired logins from the oldest, and return the number of them.
+    fn remove_expired(&mut self, now: Instant) -> usize {
+        let mut removed = 0;
+        while let Some((created_at, _)) = self.order.front() {
+            if now.duration_since(*created_at) < LOGIN_TIMEOUT {
+                break;
+            }
+            let (_, state) = self.order.pop_front().unwrap();
+            if self.logins.remove(&state).is_some() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    // Remove the login of `state`, and states already taken from `order` if it has grown to
+    // twice the logins, not to grow `order` without bound under steady logins.
+    fn remove(&mut self, state: &str) -> Option<(Instant, PendingLogin)> {
+        let login = self.logins.remove(state)?;
+        if self.order.len() > 2 * self.logins.len() {
+            let logins = &self.logins;
+            self.order.retain(|(_, state)| logins.contains_key(state));
+        }
+        Some(login)
+    }
+
+    // Drop the oldest login which has not been taken yet.
+    fn remove_oldest(&mut self) {
+        while let Some((_, state)) = self.order.pop_front() {
+            if self.logins.remove(&state).is_some() {
+                tracing::warn!("too many logins in progress, the oldest one is dropped");
+                return;
+            }
+        }
+    }
+}
+
+impl PendingLogins {
+    pub fn insert(&self, state: String, login: PendingLogin) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove_expired(now);
+        if inner.logins.len() >= MAX_PENDING_LOGINS {
+            inner.remove_oldest();
+        }
+        inner.order.push_back((now, state.clone()));
+        inner.logins.insert(state, (now, login));
+    }
+
+    /// Remove the login of `state` and return it if it has not expired.
+    pub fn take(&self, state: &str) -> Option<PendingLogin> {
+        let (created_at, login) = self.inner.lock().unwrap().remove(state)?;
+        if created_at.elapsed() >= LOGIN_TIMEOUT {
+            return None;
+        }
+        Some(login)
+    }
+
+    fn remove_expired(&self) -> usize {
+        self.inner.lock().unwrap().remove_expired(Instant::now())
+    }
+}
+
+/// Remove expired logins periodically.
+pub fn spawn_cleanup(app_state: Arc<AppState>) {
+    let shutdown = app_state.shutdown.clone();
+    app_state.shutdown.clone().spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let removed = app_state.pending_logins.remove_expired();
+            if removed > 0 {
+                tracing::debug!("{} expired logins are removed", removed);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login() -> PendingLogin {
+        PendingLogin {
+            provider: "default".to_string(),
+            pkce_verifier: PkceCodeVerifier::new("verifier".to_string()),
+            nonce: Nonce::new("nonce".to_string()),
+            return_to: "/".to_string(),
+        }
+    }
+
+    #[test]
+    fn take_returns_login_once() {
+        let logins = PendingLogins::default();
+        logins.insert("state".to_string(), login());
+        assert!(logins.take("unknown").is_none());
+        assert!(logins.take("state").is_some());
+        assert!(logins.take("state").is_none());
+    }
+
+    #[test]
+    fn insert_drops_oldest_login_over_limit() {
+        let logins = PendingLogins::default();
+        for i in 0..MAX_PENDING_LOGINS {
+            logins.insert(i.to_string(), login());
+        }
+        // the taken one is skipped, and the oldest remaining one is dropped
+        assert!(logins.take("0").is_some());
+        logins.insert("new1".to_string(), login());
+        logins.insert("new2".to_string(), login());
+        assert!(logins.take("1").is_none());
+        assert!(logins.take("2").is_some());
+        assert!(logins.take("new1").is_some());
+        assert!(logins.take("new2").is_some());
+    }
+
+    #[test]
+    fn take_compacts_order_of_taken_logins() {
+        let logins = PendingLogins::default();
+        logins.insert("kept".to_string(), login());
+        for i in 0..1000 {
+            logins.insert(i.to_string(), login());
+            assert!(logins.take(&i.to_string()).is_some());
+            let inner = logins.inner.lock().unwrap();
+            assert!(inner.order.len() <= 2 * inner.logins.len());
+        }
+        assert!(logins.take("kept").is_some());
+        assert!(logins.inner.lock().unwrap().order.is_empty());
+    }
+
+    #[test]
+    fn remove_expired_removes_old_logins() {
+        let logins = PendingLogins::default();
+        logins.insert("old".to_string(), login());
+        logins.insert("taken".to_string(), login());
+        logins.take("taken");
+        logins.insert("new".to_string(), login());
+        {
+            let mut inner = logins.inner.lock().unwrap();
+            // as if `new` is created just before `old` and `taken` expire
+            let later = inner.order[0].0 + LOGIN_TIMEOUT;
+            inner.order[1].0 = inner.order[0].0;
+            inner.order[2].0 = later;
+            assert_eq!(inner.remove_expired(later), 1);
+            assert_eq!(inner.order.len(), 1);
+        }
+        assert!(logins.take("new").is_some());
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::http::Method;
+use chrono::{Datelike, Timelike};
+use jmespath::{compile, Expression, Rcvar, Variable};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api_tokens::TokenScope;
+use crate::cmd::{BoxError, Config, PolicyConfig};
+use crate::middlewares::AuthenticatedUser;
+use crate::reload::ReloadableState;
+
+/// Actions on machines controlled by `allow_*` rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    View,
+    Start,
+    Stop,
+}
+impl Action {
+    /// Actions which change the machine, listed in the responses.
+    pub const OPERATIONS: [Action; 2] = [Action::Start, Action::Stop];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::View => "view",
+            Action::Start => "start",
+            Action::Stop => "stop",
+        }
+    }
+}
+
+// Compiled `allow_*` rules. `None` means the rule is not set.
+struct Policy {
+    view: Option<Expression<'static>>,
+    start: Option<Expression<'static>>,
+    stop: Option<Expression<'static>>,
+}
+impl Policy {
+    fn compile(config: &PolicyConfig) -> Result<Self, BoxError> {
+        let compile_opt = |key: &str, expr: &Option<String>| {
+            expr.as_deref()
+                .map(compile)
+                .transpose()
+                .map_err(|e| format!("{} should be JMESPath format: {}", key, e))
+        };
+        Ok(Policy {
+            view: compile_opt("allow_view", &config.allow_view)?,
+            start: compile_opt("allow_start", &config.allow_start)?,
+            stop: compile_opt("allow_stop", &config.allow_stop)?,
+        })
+    }
+
+    fn rule(&self, action: Action) -> Option<&Expression<'static>> {
+        match action {
+            Action::View => self.view.as_ref(),
+            Action::Start => self.start.as_ref(),
+            Action::Stop => self.stop.as_ref(),
+        }
+    }
+}
+
+/// `allow_*` rules of all machines and the default policy.
+pub struct Policies {
+    default: Policy,
+    machines: HashMap<String, Policy>,
+}
+impl Policies {
+    pub fn build(config: &Config) -> Result<Self, BoxError> {
+        let default = Policy::compile(&config.default_policy)
+            .map_err(|e| format!("default_policy: {}", e))?;
+        let mut machines = HashMap::new();
+        for driver_conf in &config.drivers {
+            let policy = Policy::compile(driver_conf.policy())
+                .map_err(|e| format!("driver {:?}: {}", driver_conf.name(), e))?;
+            machines.insert(driver_conf.name().to_string(), policy);
+        }
+        Ok(Policies { default, machines })
+    }
+
+    // The rule of the machine if set, otherwise the one of the default policy.
+    fn rule(&self, machine: &str, action: Action) -> Option<&Expression<'static>> {
+        self.machines
+            .get(machine)
+            .and_then(|p| p.rule(action))
+            .or_else(|| self.default.rule(action))
+    }
+}
+
+/// Evaluates `role_attribute_path` and `allow_*` rules for a request of the user.
+///
+/// The rules are evaluated against the document below. The claims are also placed at the top
+/// level, so that rules written only with claims (e.g. `contains(roles, 'admin')`) keep working.
+///
+/// ```json
+/// {
+///   "claims": {"sub": "...", "roles": ["..."]},
+///   "request": {"machine": "server01", "action": "start", "method": "PUT", "path": "/api/servers/start"},
+///   "now": {"timestamp": 1700000000, "year": 2023, "month": 11, "day": 15, "hour": 7,
+///           "minute": 13, "weekday": "wed", "offset": "+09:00"},
+///   "machine": {"name": "server01", "display_name": null, "description": null, "tags": ["sandbox"]}
+/// }
+/// ```
+///
+/// `now` is the local time of the server. `request.machine`, `request.action` and `machine` are
+/// set when the rules are evaluated for an action on a machine: listing machines (`view`),
+/// starting and stopping them, and listing the actions allowed on them. They are null only when
+/// `role_attribute_path` is evaluated without a machine by `has_role`.
+/// MEMO: before handlers, requests are rejected only if `has_role` is false, because the target
+/// is not known there. So rules depending on the machine (e.g. `machine.tags`) work.
+pub struct Authorizer {
+    reloadable: Arc<ReloadableState>,
+    provider: String,
+    claims: serde_json::Value,
+    scope: Option<TokenScope>,
+    method: String,
+    path: String,
+    now: serde_json::Value,
+}
+impl Authorizer {
+    pub fn new(
+        reloadable: Arc<ReloadableState>,
+        user: &AuthenticatedUser,
+        method: &Method,
+        path: &str,
+    ) -> Self {
+        let now = chrono::Local::now();
+        Authorizer {
+            reloadable,
+            provider: user.provider.clone(),
+            claims: user.claims.clone(),
+            scope: user.scope.clone(),
+            method: method.to_string(),
+            path: path.to_string(),
+            now: json!({
+                "timestamp": now.timestamp(),
+                "year": now.year(),
+                "month": now.month(),
+                "day": now.day(),
+                "hour": now.hour(),
+                "minute": now.minute(),
+                "weekday": now.weekday().to_string().to_lowercase(),
+                "offset": now.offset().to_string(),
+            }),
+        }
+    }
+
+    /// Whether `role_attribute_path` of the provider allows the user for something: a request
+    /// without a machine, or some action on some machine.
+    pub fn has_role(&self) -> bool {
+        self.has_role_in(self.document(None))
+            || self.reloadable.config.drivers.iter().any(|c| {
+                [Action::View, Action::Start, Action::Stop]
+                    .into_iter()
+                    .any(|action| self.has_role_in(self.document(Some((c.name(), action)))))
+            })
+    }
+
+    /// Whether the user may do `action` on `machine`. Both `role_attribute_path` and the
+    /// `allow_*` rule must be true. If the rule is not set, only the former is checked.
+    /// Requests by API tokens are also limited to the scope of the token.
+    pub fn is_allowed(&self, machine: &str, action: Action) -> bool {
+        if self
+            .scope
+            .as_ref()
+            .is_some_and(|scope| !scope.allows(machine, action))
+        {
+            return false;
+        }
+        let document = self.document(Some((machine, action)));
+        self.has_role_in(document.clone())
+            && self
+                .reloadable
+                .policies
+                .rule(machine, action)
+                .is_none_or(|rule| evaluate(rule, document))
+    }
+
+    /// Actions in `Action::OPERATIONS` the user may do on `machine`.
+    pub fn allowed_operations(&self, machine: &str) -> Vec<Action> {
+        Action::OPERATIONS
+            .into_iter()
+            .filter(|action| self.is_allowed(machine, *action))
+            .collect()
+    }
+
+    // MEMO: users of providers removed from the config are denied.
+    fn has_role_in(&self, document: Option<Rcvar>) -> bool {
+        self.reloadable
+            .role_attribute_path_exprs
+            .get(&self.provider)
+            .is_some_and(|expr| evaluate(expr, document))
+    }
+
+    fn document(&self, target: Option<(&str, Action)>) -> Option<Rcvar> {
+        let machine = target.and_then(|(name, _)| {
+            self.reloadable
+                .config
+                .drivers
+                .iter()
+                .find(|c| c.name() == name)
+        });
+        let mut document = self.claims.as_object().cloned().unwrap_or_default();
+        // MEMO: the context is set after the claims, so that tokens cannot forge it
+        // by claims of the same names.
+        document.insert("claims".to_string(), self.claims.clone());
+        document.insert(
+            "request".to_string(),
+            json!({
+                "machine": target.map(|(name, _)| name),
+                "action": target.map(|(_, action)| action.as_str()),
+                "method": self.method,
+                "path": self.path,
+            }),
+        );
+        document.insert("now".to_string(), self.now.clone());
+        document.insert(
+            "machine".to_string(),
+            machine.map_or(serde_json::Value::Null, |c| {
+                let metadata = c.metadata();
+                json!({
+                    "name": c.name(),
+                    "display_name": metadata.display_name,
+                    "description": metadata.description,
+                    "tags": metadata.tags,
+                })
+            }),
+        );
+        Variable::from_serializable(document)
+            .map(Rcvar::new)
+            .inspect_err(|e| tracing::warn!("claims cannot be evaluated: {:?}", e))
+            .ok()
+    }
+}
+
+// MEMO: errors and non-boolean results are denied.
+fn evaluate(expr: &Expression<'static>, document: Option<Rcvar>) -> bool {
+    document
+        .and_then(|document| expr.search(document).ok())
+        .and_then(|v| v.as_boolean())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_tokens::TokenScope;
+
+    const CONFIG: &str = r#"
+url = "http://127.0.0.1:8080"
+[oidc]
+provider_url = "http://127.0.0.1:8090/"
+client_id = "cid"
+client_secret = "csecret"
+# admins may do anything, and interns may use sandbox machines
+role_attribute_path = "contains(roles, 'admin') || (contains(roles, 'intern') && contains(machine.tags, 'sandbox'))"
+[default_policy]
+allow_stop = "contains(roles, 'admin')"
+[[drivers]]
+type = "Debug"
+name = "sandbox01"
+tags = ["sandbox"]
+[[drivers]]
+type = "Debug"
+name = "prod01"
+allow_start = "request.method == 'PUT'"
+"#;
+
+    fn authorizer(roles: &[&str], scope: Option<TokenScope>) -> Authorizer {
+        let config = Config::parse(CONFIG).unwrap();
+        let reloadable = Arc::new(ReloadableState::build(config, None).unwrap());
+        let user = AuthenticatedUser {
+            provider: "default".to_string(),
+            claims: json!({"sub": "user1", "roles": roles}),
+            scope,
+            csrf_token: None,
+        };
+        Authorizer::new(reloadable, &user, &Method::PUT, "/api/servers/start")
+    }
+
+    #[test]
+    fn role_attribute_path_is_evaluated_with_machine() {
+        let intern = authorizer(&["intern"], None);
+        assert!(intern.has_role());
+        assert!(intern.is_allowed("sandbox01", Action::View));
+        assert!(intern.is_allowed("sandbox01", Action::Start));
+        assert!(!intern.is_allowed("prod01", Action::View));
+        assert!(!intern.is_allowed("prod01", Action::Start));
+    }
+
+    #[test]
+    fn users_without_role_are_denied() {
+        let guest = authorizer(&["guest"], None);
+        assert!(!guest.has_role());
+        assert!(!guest.is_allowed("sandbox01", Action::View));
+        assert!(guest.allowed_operations("sandbox01").is_empty());
+    }
+
+    #[test]
+    fn rules_of_machine_take_precedence_over_default_policy() {
+        let admin = authorizer(&["admin"], None);
+        assert!(admin.is_allowed("prod01", Action::Start));
+        assert!(admin.is_allowed("prod01", Action::Stop));
+        let intern = authorizer(&["intern"], None);
+        // allow_stop of the default policy denies interns
+        assert_eq!(intern.allowed_operations("sandbox01"), vec![Action::Start]);
+    }
+
+    #[test]
+    fn unknown_provider_is_denied() {
+        let mut admin = authorizer(&["admin"], None);
+        admin.provider = "removed".to_string();
+        assert!(!admin.has_role());
+        assert!(!admin.is_allowed("sandbox01", Action::View));
+    }
+
+    #[test]
+    fn api_token_scope_limits_actions() {
+        let scope = TokenScope {
+            read_only: true,
+            machines: None,
+        };
+        let admin = authorizer(&["admin"], Some(scope));
+        assert!(admin.is_allowed("prod01", Action::View));
+        assert!(admin.allowed_operations("prod01").is_empty());
+    }
+
+    #[test]
+    fn claims_cannot_forge_context() {
+        let config = Config::parse(CONFIG).unwrap();
+        let reloadable = Arc::new(ReloadableState::build(config, None).unwrap());
+        let user = AuthenticatedUser {
+            provider: "default".to_string(),
+            claims: json!({"roles": ["intern"], "machine": {"tags": ["sandbox"]}}),
+            scope: None,
+            csrf_token: None,
+        };
+        let intern = Authorizer::new(reloadable, &user, &Method::PUT, "/api/servers/start");
+        assert!(!intern.is_allowed("prod01", Action::Start));
+    }
+
+    #[test]
+    fn evaluate_denies_non_boolean_results() {
+        let document = Variable::from_serializable(json!({"a": "true", "b": true}))
+            .map(Rcvar::new)
+            .ok();
+        assert!(!evaluate(&compile("a").unwrap(), document.clone()));
+        assert!(evaluate(&compile("b").unwrap(), document.clone()));
+        assert!(!evaluate(&compile("missing").unwrap(), document));
+        assert!(!evaluate(&compile("b").unwrap(), None));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::{header, HeaderMap, HeaderName};
+
+use crate::AppState;
+
+// Upper bound of keys tracked by a limiter, not to grow memory by requests from many addresses
+const MAX_KEYS: usize = 100_000;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Logins each client address may start in `LOGIN_START_WINDOW`
+pub const MAX_LOGIN_STARTS: u32 = 30;
+pub const LOGIN_START_WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts events (e.g. login attempts) per key (e.g. client address) in fixed time windows.
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    counts: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        RateLimiter {
+            max,
+            window,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count an event of `key`, and return whether it is within the limit.
+    /// While too many keys are tracked, the oldest ones are forgotten.
+    // MEMO: new keys are not rejected when the table is full, not to let clients with many
+    // addresses lock out everyone else.
+    pub fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let mut counts = self.counts.lock().unwrap();
+        if counts.len() >= MAX_KEYS && !counts.contains_key(key) {
+            counts.retain(|_, (started_at, _)| now.duration_since(*started_at) < self.window);
+            if counts.len() >= MAX_KEYS {
+                // Forget the oldest quarter at once, not to scan the table on every new key
+                let mut started: Vec<Instant> = counts.values().map(|(s, _)| *s).collect();
+                let (_, threshold, _) = started.select_nth_unstable(MAX_KEYS / 4);
+                let threshold = *threshold;
+                counts.retain(|_, (started_at, _)| *started_at > threshold);
+                tracing::warn!("too many clients are rate-limited, the oldest ones are forgotten");
+            }
+        }
+        let (started_at, count) = counts.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(*started_at) >= self.window {
+            (*started_at, *count) = (now, 0);
+        }
+        *count = count.saturating_add(1);
+        *count <= self.max
+    }
+
+    /// Forget the events of `key`, e.g. after a successful login.
+    pub fn reset(&self, key: &str) {
+        self.counts.lock().unwrap().remove(key);
+    }
+
+    pub(crate) fn remove_expired(&self) -> usize {
+        let mut counts = self.counts.lock().unwrap();
+        let before = counts.len();
+        counts.retain(|_, (started_at, _)| started_at.elapsed() < self.window);
+        before - counts.len()
+    }
+}
+
+/// The key to rate-limit `client` by. IPv6 clients are limited by the /64 prefix, because a
+/// single host usually has the whole /64 and can use any address in it.
+pub fn client_key(client: IpAddr) -> String {
+    match client {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let prefix = Ipv6Addr::from(u128::from(ip) & !(u128::from(u64::MAX)));
+            format!("{}/64", prefix)
+        }
+    }
+}
+
+/// The address of the client sending the request to `peer`.
+/// If `peer` is one of `trusted_proxies`, the client is the nearest address in `X-Forwarded-For`
+/// (or `Forwarded` if it is not set) which is not a trusted proxy.
+// MEMO: only addresses appended by trusted proxies are used, because clients can set any
+// addresses to the left of them.
+pub fn client_ip(trusted_proxies: &[IpAddr], peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.contains(&ip.to_canonical());
+    let mut client = peer.ip().to_canonical();
+    if !is_trusted(&client) {
+        return client;
+    }
+    for hop in forwarded_for(headers).into_iter().rev() {
+        // MEMO: obfuscated identifiers (e.g. `unknown`) are not addresses to rate-limit by.
+        let Some(ip) = hop else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !is_trusted(&client) {
+            break;
+        }
+    }
+    client
+}
+
+// Addresses from the client to the nearest proxy in `X-Forwarded-For` or `Forwarded`.
+// `None` is an identifier which is not an address.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let x_forwarded_for = values(HeaderName::from_static("x-forwarded-for"));
+    if !x_forwarded_for.is_empty() {
+        return x_forwarded_for.into_iter().map(parse_node).collect();
+    }
+    values(header::FORWARDED)
+        .into_iter()
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value.trim_matches('"')))
+            })
+        })
+        .collect()
+}
+
+// Parse `192.0.2.1`, `192.0.2.1:8080`, `2001:db8::1` or `[2001:db8::1]:8080`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.split(']').next()?.parse().ok()
+}
+
+/// Remove counts of past windows periodically.
+pub fn spawn_cleanup(app_state: Arc<AppState>) {
+    let shutdown = app_state.shutdown.clone();
+    app_state.shutdown.clone().spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let removed = app_state.login_limiter.remove_expired()
+                + app_state.local_login_throttle.remove_expired();
+            if removed > 0 {
+                tracing::debug!("{} expired rate limit counts are removed", removed);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_allows_up_to_max_per_key() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check("a"));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        assert!(limiter.check("b"));
+    }
+
+    #[test]
+    fn reset_forgets_events() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        limiter.reset("a");
+        assert!(limiter.check("a"));
+    }
+
+    #[test]
+    fn counts_expire_after_window() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.remove_expired(), 1);
+        assert!(limiter.check("a"));
+    }
+
+    #[test]
+    fn check_forgets_oldest_keys_instead_of_rejecting_new_ones() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        for i in 0..MAX_KEYS {
+            assert!(limiter.check(&i.to_string()));
+        }
+        assert!(limiter.check("new"));
+        assert!(!limiter.check("new"));
+        assert!(limiter.counts.lock().unwrap().len() <= MAX_KEYS);
+        // the oldest key is forgotten, the newest ones are kept
+        assert!(limiter.check("0"));
+        assert!(!limiter.check(&(MAX_KEYS - 1).to_string()));
+    }
+
+    #[test]
+    fn client_key_groups_ipv6_addresses_by_64_prefix() {
+        let key = |ip: &str| client_key(ip.parse().unwrap());
+        assert_eq!(key("192.0.2.1"), "192.0.2.1");
+        assert_eq!(key("2001:db8:1:2:aaaa::1"), "2001:db8:1:2::/64");
+        assert_eq!(key("2001:db8:1:2:bbbb::2"), key("2001:db8:1:2:aaaa::1"));
+        assert_ne!(key("2001:db8:1:3::1"), key("2001:db8:1:2::1"));
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn client_ip_ignores_forwarded_headers_from_untrusted_peers() {
+        let trusted: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap()];
+        let peer: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let headers = headers(&[("x-forwarded-for", "198.51.100.1")]);
+        assert_eq!(client_ip(&trusted, peer, &headers), peer.ip());
+        assert_eq!(client_ip(&[], peer, &headers), peer.ip());
+    }
+
+    #[test]
+    fn client_ip_takes_the_nearest_untrusted_address_behind_proxies() {
+        let trusted: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let client: IpAddr = "198.51.100.1".parse().unwrap();
+
+        // The address set by the client itself is not used.
+        let h = headers(&[("x-forwarded-for", "203.0.113.9, 198.51.100.1, 10.0.0.2")]);
+        assert_eq!(client_ip(&trusted, peer, &h), client);
+        let h = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+        assert_eq!(client_ip(&trusted, peer, &h), client);
+        let h = headers(&[(
+            "forwarded",
+            r#"for=203.0.113.9, for="198.51.100.1:1234";proto=https, for=10.0.0.2"#,
+        )]);
+        assert_eq!(client_ip(&trusted, peer, &h), client);
+        let h = headers(&[("forwarded", r#"for="[2001:db8::1]""#)]);
+        assert_eq!(
+            client_ip(&trusted, peer, &h),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        // Clients behind the same proxy are limited separately.
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        for client in ["198.51.100.1", "198.51.100.2"] {
+            let h = headers(&[("x-forwarded-for", client)]);
+            assert!(limiter.check(&client_key(client_ip(&trusted, peer, &h))));
+        }
+    }
+
+    #[test]
+    fn client_ip_falls_back_to_the_proxy_without_usable_addresses() {
+        let trusted: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap()];
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        assert_eq!(client_ip(&trusted, peer, &HeaderMap::new()), peer.ip());
+        let h = headers(&[("forwarded", "for=unknown")]);
+        assert_eq!(client_ip(&trusted, peer, &h), peer.ip());
+    }
+}
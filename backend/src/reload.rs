@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use jmespath::{compile, Expression};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+
+use crate::cmd::{BoxError, Config, ServerConfig, LOCAL_PROVIDER_NAME};
+use crate::drivers::{new_driver, traits::PowerManagerTrait};
+use crate::policy::Policies;
+use crate::AppState;
+
+// Wait for a while after a file event because editors write files in several steps.
+const DEBOUNCE_PERIOD: Duration = Duration::from_millis(500);
+
+/// The part of the application state rebuilt from the config file on reload.
+pub struct ReloadableState {
+    pub config: Config,
+    pub drivers: HashMap<String, Arc<dyn PowerManagerTrait>>,
+    // role_attribute_path of each OIDC provider and local_auth by the provider name
+    pub role_attribute_path_exprs: HashMap<String, Expression<'static>>,
+    pub policies: Policies,
+}
+impl ReloadableState {
+    /// Build the state from `config`. Drivers whose config is unchanged from `previous` are
+    /// reused as they are, so that their connections and internal state are kept.
+    pub fn build(config: Config, previous: Option<&ReloadableState>) -> Result<Self, BoxError> {
+        let mut drivers = HashMap::<String, Arc<dyn PowerManagerTrait>>::new();
+        for driver_conf in &config.drivers {
+            let name = driver_conf.name().to_string();
+            let reused = previous.and_then(|p| {
+                p.config
+                    .drivers
+                    .iter()
+                    .find(|c| c.name() == name)
+                    // MEMO: changes of the policy only do not rebuild the driver
+                    .filter(|c| c.without_policy() == driver_conf.without_policy())
+                    .and_then(|_| p.drivers.get(&name).cloned())
+            });
+            let driver = match reused {
+                Some(driver) => driver,
+                None => new_driver(driver_conf)
+                    .map_err(|e| format!("driver {:?} could not be initialized: {}", name, e))?,
+            };
+            drivers.insert(name, driver);
+        }
+
+        let mut role_attribute_path_exprs = HashMap::new();
+        for oidc in config.oidc_configs() {
+            let expr = compile(&oidc.role_attribute_path).map_err(|e| {
+                format!(
+                    "role_attribute_path of {:?} should be JMESPath format: {}",
+                    oidc.name, e
+                )
+            })?;
+            role_attribute_path_exprs.insert(oidc.name.clone(), expr);
+        }
+        if let Some(local_auth) = &config.local_auth {
+            let expr = compile(&local_auth.role_attribute_path).map_err(|e| {
+                format!(
+                    "role_attribute_path of local_auth should be JMESPath format: {}",
+                    e
+                )
+            })?;
+            role_attribute_path_exprs.insert(LOCAL_PROVIDER_NAME.to_string(), expr);
+        }
+        let policies = Policies::build(&config)?;
+
+        Ok(ReloadableState {
+            config,
+            drivers,
+            role_attribute_path_exprs,
+            policies,
+        })
+    }
+}
+
+/// Reload the config file and swap the reloadable state. If the new config is invalid or
+/// changes settings applied only on startup, the running state is left in place.
+pub fn reload(app_state: &AppState, config_path: &Path) -> Result<(), BoxError> {
+    let config = Config::load(config_path)?;
+    let previous = app_state.reloadable();
+    if config == previous.config {
+        tracing::info!("config is not changed, skip reloading");
+        return Ok(());
+    }
+    // MEMO: role_attribute_path and display_name of OIDC providers are applied without restart.
+    let oidc_clients = |config: &Config| {
+        config
+            .oidc_configs()
+            .map(|c| {
+                (
+                    c.name.clone(),
+                    c.provider_url.clone(),
+                    c.client_id.clone(),
+                    c.client_secret.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    // MEMO: trusted_proxies are read on each request.
+    let server = |config: &Config| ServerConfig {
+        trusted_proxies: vec![],
+        ..config.server.clone()
+    };
+    // MEMO: these are rejected instead of warned, because the new values in the running config
+    // would be used partly (e.g. `url` by origin checks) while the rest still uses the old ones.
+    let mut restart_required = vec![];
+    if config.url != previous.config.url {
+        restart_required.push("url");
+    }
+    if oidc_clients(&config) != oidc_clients(&previous.config) {
+        restart_required.push("OIDC providers");
+    }
+    if server(&config) != server(&previous.config) {
+        restart_required.push("server");
+    }
+    if config.storage != previous.config.storage {
+        restart_required.push("storage");
+    }
+    if !restart_required.is_empty() {
+        return Err(format!(
+            "changes of {} settings require restart",
+            restart_required.join(", ")
+        )
+        .into());
+    }
+
+    let next = ReloadableState::build(config, Some(&previous))?;
+    let added: Vec<&String> = next
+        .drivers
+        .keys()
+        .filter(|name| !previous.drivers.contains_key(*name))
+        .collect();
+    let removed: Vec<&String> = previous
+        .drivers
+        .keys()
+        .filter(|name| !next.drivers.contains_key(*name))
+        .collect();
+    let changed: Vec<&String> = next
+        .drivers
+        .iter()
+        .filter(|(name, driver)| {
+            previous
+                .drivers
+                .get(*name)
+                .is_some_and(|p| !Arc::ptr_eq(p, driver))
+        })
+        .map(|(name, _)| name)
+        .collect();
+    tracing::info!(
+        "config is reloaded: added={:?}, removed={:?}, changed={:?}",
+        added,
+        removed,
+        changed
+    );
+
+    *app_state.reloadable.write().unwrap() = Arc::new(next);
+    Ok(())
+}
+
+/// Reload the config file when it is modified or SIGHUP is received.
+pub fn spawn_watcher(
+    app_state: Arc<AppState>,
+    config_path: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (watcher, mut rx) = watch_files(std::slice::from_ref(&config_path))?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    let shutdown = app_state.shutdown.clone();
+    app_state.shutdown.clone().spawn(async move {
+        // keep the watcher alive as long as this task
+        let _watcher = watcher;
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                Some(()) = recv_debounced(&mut rx) => {
+                    tracing::info!("config file is modified, reloading");
+                }
+                Some(()) = sighup.recv() => {
+                    tracing::info!("SIGHUP is received, reloading");
+                }
+                else => break,
+            }
+
+            let app_state = app_state.clone();
+            let config_path = config_path.clone();
+            match tokio::task::spawn_blocking(move || reload(&app_state, &config_path)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    tracing::error!("failed to reload config, keep running config: {}", e)
+                }
+                Err(e) => tracing::error!("failed to reload config, keep running config: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Watch `paths` and send to the returned channel when some of them are modified.
+/// The returned watcher must be kept alive while watching.
+pub fn watch_files(
+    paths: &[PathBuf],
+) -> Result<(RecommendedWatcher, mpsc::UnboundedReceiver<()>), notify::Error> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    // MEMO: watch the parent directories instead of the files themselves,
+    // because the files may be replaced by renaming (e.g. Kubernetes ConfigMap/Secret, vim).
+    let file_names: Vec<OsString> = paths
+        .iter()
+        .filter_map(|p| p.file_name().map(|s| s.to_os_string()))
+        .collect();
+    let mut watch_dirs: Vec<PathBuf> = paths
+        .iter()
+        .map(|p| match p.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+    watch_dirs.sort();
+    watch_dirs.dedup();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            // ignore events of other files except symlink swaps such as `..data`
+            let is_target = !event.kind.is_access()
+                && event.paths.iter().any(|p| {
+                    p.file_name().is_some_and(|s| {
+                        file_names.iter().any(|name| name == s)
+                            || s.to_string_lossy().starts_with("..")
+                    })
+                });
+            if is_target {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    for dir in &watch_dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok((watcher, rx))
+}
+
+/// Receive from the channel returned by `watch_files`, and merge the following events
+/// because editors write files in several steps.
+pub async fn recv_debounced(rx: &mut mpsc::UnboundedReceiver<()>) -> Option<()> {
+    rx.recv().await?;
+    tokio::time::sleep(DEBOUNCE_PERIOD).await;
+    while rx.try_recv().is_ok() {}
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+url = "https://launcher.example.com"
+[[local_auth.users]]
+username = "admin"
+password_hash = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaGhhc2hoYXNo"
+[[drivers]]
+type = "Debug"
+name = "kept"
+[[drivers]]
+type = "Debug"
+name = "policy-changed"
+[[drivers]]
+type = "Debug"
+name = "changed"
+[[drivers]]
+type = "Debug"
+name = "removed"
+"#;
+
+    // Start with `CONFIG`, reload `next`, and return the states before and after the reload.
+    fn reload_with(
+        name: &str,
+        next: &str,
+    ) -> (
+        Result<(), BoxError>,
+        Arc<ReloadableState>,
+        Arc<ReloadableState>,
+    ) {
+        let app_state = AppState::for_tests(Config::parse(CONFIG).unwrap());
+        let previous = app_state.reloadable();
+        let path = std::env::temp_dir().join(format!(
+            "machine-launcher-reload-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, next).unwrap();
+        let result = reload(&app_state, &path);
+        std::fs::remove_file(&path).unwrap();
+        (result, previous, app_state.reloadable())
+    }
+
+    #[test]
+    fn reload_keeps_state_of_unchanged_config() {
+        let (result, previous, next) = reload_with("unchanged", CONFIG);
+        assert!(result.is_ok());
+        assert!(Arc::ptr_eq(&previous, &next));
+    }
+
+    #[test]
+    fn reload_rejects_invalid_config() {
+        let (result, previous, next) = reload_with("invalid", "url = \"https://x\"\n[[drivers]]\n");
+        assert!(result.is_err());
+        assert!(Arc::ptr_eq(&previous, &next));
+
+        let invalid_name = CONFIG.replace("\"removed\"", "\"kept\"");
+        let (result, previous, next) = reload_with("duplicate", &invalid_name);
+        assert!(result.is_err());
+        assert!(Arc::ptr_eq(&previous, &next));
+    }
+
+    #[test]
+    fn reload_rejects_changes_requiring_restart() {
+        let config = format!("{}\n[server]\nlisten = [\"127.0.0.1:9999\"]\n", CONFIG)
+            .replace("https://launcher.example.com", "https://other.example.com");
+        let (result, previous, next) = reload_with("restart", &config);
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("url"), "{}", message);
+        assert!(message.contains("server"), "{}", message);
+        assert!(Arc::ptr_eq(&previous, &next));
+
+        // trusted_proxies are read on each request
+        let config = format!("{}\n[server]\ntrusted_proxies = [\"127.0.0.1\"]\n", CONFIG);
+        let (result, _, next) = reload_with("trusted_proxies", &config);
+        assert!(result.is_ok());
+        assert_eq!(next.config.server.trusted_proxies.len(), 1);
+    }
+
+    #[test]
+    fn reload_adds_removes_and_rebuilds_changed_drivers() {
+        let config = r#"
+url = "https://launcher.example.com"
+[[local_auth.users]]
+username = "admin"
+password_hash = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaGhhc2hoYXNo"
+[[drivers]]
+type = "Debug"
+name = "kept"
+[[drivers]]
+type = "Debug"
+name = "policy-changed"
+allow_view = "`true`"
+[[drivers]]
+type = "Debug"
+name = "changed"
+boot_duration_secs = 10
+[[drivers]]
+type = "Debug"
+name = "added"
+"#;
+        let (result, previous, next) = reload_with("drivers", config);
+        assert!(result.is_ok());
+        let mut names: Vec<&String> = next.drivers.keys().collect();
+        names.sort();
+        assert_eq!(names, ["added", "changed", "kept", "policy-changed"]);
+        assert!(Arc::ptr_eq(
+            &previous.drivers["kept"],
+            &next.drivers["kept"]
+        ));
+        assert!(Arc::ptr_eq(
+            &previous.drivers["policy-changed"],
+            &next.drivers["policy-changed"]
+        ));
+        assert!(!Arc::ptr_eq(
+            &previous.drivers["changed"],
+            &next.drivers["changed"]
+        ));
+    }
+}
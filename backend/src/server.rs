@@ -0,0 +1,213 @@
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::cmd::{ServerConfig, TlsConfig};
+use crate::reload::{recv_debounced, watch_files};
+use crate::shutdown::{wait_for_signal, Shutdown};
+
+/// Serve `app` on all listeners until SIGTERM/SIGINT is received or one of them fails.
+/// On the signal, stop accepting new connections and wait for running requests,
+/// power operations and background tasks up to `shutdown_timeout_secs`.
+pub async fn serve(
+    config: &ServerConfig,
+    app: Router,
+    shutdown: &Shutdown,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Sockets passed by systemd socket activation take precedence over the config
+    let mut listeners = vec![];
+    let mut listenfd = listenfd::ListenFd::from_env();
+    for i in 0..listenfd.len() {
+        if let Some(listener) = listenfd.take_tcp_listener(i)? {
+            listener.set_nonblocking(true)?;
+            listeners.push(listener);
+        }
+    }
+    if listeners.is_empty() {
+        for addr in &config.listen {
+            listeners.push(bind(*addr)?);
+        }
+    }
+
+    let tls_config = match &config.tls {
+        Some(tls) => Some(load_tls_config(tls, shutdown).await?),
+        None => None,
+    };
+
+    let handle = Handle::new();
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let addr = listener.local_addr()?;
+        // MEMO: client addresses are used to rate-limit logins.
+        let service = app
+            .clone()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        match &tls_config {
+            Some(tls_config) => {
+                tracing::info!("listening on https://{}", addr);
+                servers.spawn(
+                    axum_server::from_tcp_rustls(listener, tls_config.clone())
+                        .handle(handle.clone())
+                        .serve(service),
+                );
+            }
+            None => {
+                tracing::info!("listening on http://{}", addr);
+                servers.spawn(
+                    axum_server::from_tcp(listener)
+                        .handle(handle.clone())
+                        .serve(service),
+                );
+            }
+        }
+    }
+
+    tokio::select! {
+        res = wait_for_signal() => res?,
+        Some(res) = servers.join_next() => {
+            // a server stopped unexpectedly
+            res??;
+        }
+    }
+
+    let timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let deadline = Instant::now() + timeout;
+    handle.graceful_shutdown(Some(timeout));
+    let (servers_result, _) = tokio::join!(
+        async {
+            while let Some(res) = servers.join_next().await {
+                res??;
+            }
+            Ok::<(), Box<dyn std::error::Error>>(())
+        },
+        shutdown.drain(deadline),
+    );
+    servers_result?;
+    tracing::info!("shutdown completed");
+
+    Ok(())
+}
+
+fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // MEMO: listen on IPv6 only, so that the same port can be listened on IPv4 too.
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+// Load the certificate and the private key, and reload them when the files are modified.
+async fn load_tls_config(
+    tls: &TlsConfig,
+    shutdown: &Shutdown,
+) -> Result<RustlsConfig, Box<dyn std::error::Error>> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let tls_config = RustlsConfig::from_pem_file(&tls.cert_file, &tls.key_file).await?;
+
+    let (watcher, mut rx) = watch_files(&[(&tls.cert_file).into(), (&tls.key_file).into()])?;
+    let tls = tls.clone();
+    let reloaded = tls_config.clone();
+    let cancelled = shutdown.clone();
+    shutdown.spawn(async move {
+        // keep the watcher alive as long as this task
+        let _watcher = watcher;
+        loop {
+            tokio::select! {
+                _ = cancelled.cancelled() => break,
+                Some(()) = recv_debounced(&mut rx) => {}
+                else => break,
+            }
+            match reloaded
+                .reload_from_pem_file(&tls.cert_file, &tls.key_file)
+                .await
+            {
+                Ok(()) => tracing::info!("TLS certificate is reloaded"),
+                Err(e) => tracing::error!(
+                    "failed to reload TLS certificate, keep current certificate: {}",
+                    e
+                ),
+            }
+        }
+    });
+
+    Ok(tls_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    // Self-signed certificate of `localhost` and 127.0.0.1 valid until 2125
+    const CERT_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls-cert.pem");
+    const KEY_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls-key.pem");
+
+    #[test]
+    fn bind_listens_on_same_port_of_ipv4_and_ipv6() {
+        let v4 = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let v6 = bind(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port))).unwrap();
+        assert_eq!(v6.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn tls_config_serves_https() {
+        let shutdown = Shutdown::default();
+        let tls = TlsConfig {
+            cert_file: CERT_FILE.to_string(),
+            key_file: KEY_FILE.to_string(),
+        };
+        let tls_config = load_tls_config(&tls, &shutdown).await.unwrap();
+        let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        tokio::spawn(
+            axum_server::from_tcp_rustls(listener, tls_config).serve(app.into_make_service()),
+        );
+
+        let cert = reqwest::Certificate::from_pem(&std::fs::read(CERT_FILE).unwrap()).unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(cert)
+            .build()
+            .unwrap();
+        let body = client
+            .get(format!("https://localhost:{}/", port))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "ok");
+        // the watcher of the certificate files stops on shutdown
+        shutdown
+            .drain(Instant::now() + Duration::from_secs(5))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn tls_config_rejects_invalid_files() {
+        let shutdown = Shutdown::default();
+        // the certificate is not a private key
+        let tls = TlsConfig {
+            cert_file: CERT_FILE.to_string(),
+            key_file: CERT_FILE.to_string(),
+        };
+        assert!(load_tls_config(&tls, &shutdown).await.is_err());
+        let tls = TlsConfig {
+            cert_file: "/nonexistent/cert.pem".to_string(),
+            key_file: KEY_FILE.to_string(),
+        };
+        assert!(load_tls_config(&tls, &shutdown).await.is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::prelude::*;
+use openidconnect::{Nonce, OAuth2TokenResponse, RefreshToken, TokenResponse};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::api_tokens;
+use crate::store::SessionRecord;
+use crate::{AppState, Error};
+use machine_launcher_utils::all_claims_from_jwt;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Renew sessions by the refresh token when their ID tokens expire within this
+const RENEW_BEFORE_SECS: i64 = 5 * 60;
+
+/// Locks to serialize renewals of each session.
+/// MEMO: refresh tokens may be rotated on use, and the parallel requests of the same session
+/// must not use the old one.
+#[derive(Default)]
+pub struct RenewLocks {
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+impl RenewLocks {
+    async fn lock(&self, id: &str) -> RenewGuard<'_> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone();
+        RenewGuard {
+            locks: self,
+            id: id.to_string(),
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    // Remove locks left by requests cancelled while waiting for them.
+    fn remove_unused(&self) {
+        self.locks
+            .lock()
+            .unwrap()
+            .retain(|_, lock| Arc::strong_count(lock) > 1);
+    }
+}
+
+// Releases the lock, and removes it when no other request is waiting for it.
+struct RenewGuard<'a> {
+    locks: &'a RenewLocks,
+    id: String,
+    guard: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+impl Drop for RenewGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.locks.lock().unwrap();
+        if locks
+            .get(&self.id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.id);
+        }
+    }
+}
+
+/// Create a session for the verified ID token and return the session token for the cookie.
+/// Only the hash of the token is stored.
+pub async fn create_session(
+    state: &AppState,
+    provider: &str,
+    claims: serde_json::Value,
+    id_token: String,
+    refresh_token: Option<String>,
+    expires_at: i64,
+) -> Result<String, Error> {
+    let token = generate_token();
+    let now = chrono::Utc::now().timestamp();
+    let max_lifetime_secs = state.reloadable().config.session.max_lifetime_secs;
+    let max_expires_at = now.saturating_add(max_lifetime_secs.try_into().unwrap_or(i64::MAX));
+    api_tokens::update_claims(state, provider, &claims).await;
+    let session = SessionRecord {
+        id: hash_token(&token),
+        provider: provider.to_string(),
+        claims,
+        id_token,
+        refresh_token,
+        created_at: now,
+        expires_at: expires_at.min(max_expires_at),
+        max_expires_at,
+    };
+    state
+        .store
+        .blocking(move |store| store.insert_session(&session))
+        .await?;
+    Ok(token)
+}
+
+/// Find the session of the token in the cookie, renewing it by the refresh token if its
+/// ID token expires soon. Expired sessions are not returned.
+pub async fn find_session(state: &AppState, token: &str) -> Result<Option<SessionRecord>, Error> {
+    let id = hash_token(token);
+    let now = chrono::Utc::now().timestamp();
+    let Some(session) = load_session(state, &id, now).await? else {
+        return Ok(None);
+    };
+    if !needs_renewal(&session, now) {
+        return Ok(Some(session).filter(|s| s.expires_at > now));
+    }
+
+    let _guard = state.renew_locks.lock(&id).await;
+    // the session may have been renewed by another request while waiting for the lock
+    let Some(session) = load_session(state, &id, now).await? else {
+        return Ok(None);
+    };
+    if !needs_renewal(&session, now) {
+        return Ok(Some(session).filter(|s| s.expires_at > now));
+    }
+    match renew_session(state, &session, now).await {
+        Ok(renewed) => {
+            let updated = renewed.clone();
+            state
+                .store
+                .blocking(move |store| store.update_session(&updated))
+                .await?;
+            api_tokens::update_claims(state, &renewed.provider, &renewed.claims).await;
+            tracing::debug!("session is renewed until {}", renewed.expires_at);
+            Ok(Some(renewed))
+        }
+        Err(e) => {
+            tracing::warn!("failed to renew session: {}", e);
+            Ok(Some(session).filter(|s| s.expires_at > now))
+        }
+    }
+}
+
+async fn load_session(
+    state: &AppState,
+    id: &str,
+    now: i64,
+) -> Result<Option<SessionRecord>, Error> {
+    let id = id.to_string();
+    state
+        .store
+        .blocking(move |store| store.find_session(&id, now))
+        .await
+}
+
+fn needs_renewal(session: &SessionRecord, now: i64) -> bool {
+    session.refresh_token.is_some()
+        && session.expires_at - now < RENEW_BEFORE_SECS
+        && session.expires_at < session.max_expires_at
+}
+
+// Get new tokens from the OIDC provider by the refresh token.
+async fn renew_session(
+    state: &AppState,
+    session: &SessionRecord,
+    now: i64,
+) -> Result<SessionRecord, Error> {
+    let provider = state.oidc_providers.get(&session.provider).ok_or_else(|| {
+        Error::Unauthorized(format!("OIDC provider {:?} is not found", session.provider).into())
+    })?;
+    let refresh_token = RefreshToken::new(session.refresh_token.clone().unwrap_or_default());
+    let resp = provider
+        .client
+        .exchange_refresh_token(&refresh_token)
+        .request_async(state.oidc_providers.http_client())
+        .await
+        .map_err(|e| Error::Unauthorized(format!("Token refresh failed: {:?}", e).into()))?;
+
+    let mut renewed = session.clone();
+    if let Some(refresh_token) = resp.refresh_token() {
+        renewed.refresh_token = Some(refresh_token.secret().clone());
+    }
+    match resp.id_token() {
+        Some(id_token) => {
+            // MEMO: ID tokens issued by refresh have no nonce or the one of the login,
+            // which is verified on the login.
+            let nonce_verifier = |_: Option<&Nonce>| Ok(());
+            let expires_at = state
+                .oidc_providers
+                .verify_id_token(&provider, id_token, nonce_verifier)
+                .await
+                .map_err(|e| Error::Unauthorized(format!("Id token is invalid: {:?}", e).into()))?
+                .expiration()
+                .timestamp();
+            let id_token = id_token.to_string();
+            let claims = serde_json::Value::Object(
+                all_claims_from_jwt(&id_token)
+                    .map_err(|e| Error::Unauthorized(e.to_string().into()))?
+                    .into_iter()
+                    .collect(),
+            );
+            if claims.get("sub") != session.claims.get("sub") {
+                return Err(Error::Unauthorized(
+                    "Id token is issued for another user".into(),
+                ));
+            }
+            renewed.claims = claims;
+            renewed.id_token = id_token;
+            renewed.expires_at = expires_at;
+        }
+        None => {
+            // some providers return no ID token on refresh, then extend by the access token
+            let expires_in = resp.expires_in().ok_or_else(|| {
+                Error::Unauthorized("Token refresh returned no expiration".into())
+            })?;
+            renewed.expires_at = now.saturating_add(expires_in.as_secs().try_into().unwrap_or(0));
+        }
+    }
+    renewed.expires_at = renewed.expires_at.min(renewed.max_expires_at);
+    Ok(renewed)
+}
+
+/// Delete the session of the token in the cookie and return it, to log out.
+pub async fn take_session(state: &AppState, token: &str) -> Result<Option<SessionRecord>, Error> {
+    let id = hash_token(token);
+    state
+        .store
+        .blocking(move |store| store.take_session(&id))
+        .await
+}
+
+/// Generate a random token of 32 bytes encoded in base64url.
+pub(crate) fn generate_token() -> String {
+    let mut token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    BASE64_URL_SAFE_NO_PAD.encode(token)
+}
+
+/// CSRF token of the session, required in `X-CSRF-Token` header on changes by the session
+/// cookie. It is derived from the session token, so it is not stored, and sites without the
+/// cookie cannot compute it.
+pub fn csrf_token(session_token: &str) -> String {
+    hash_token(&format!("csrf:{}", session_token))
+}
+
+/// Hash the token to store, not to leak valid tokens by the database file.
+pub(crate) fn hash_token(token: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Delete expired sessions and remove unused renewal locks periodically.
+pub fn spawn_cleanup(app_state: Arc<AppState>) {
+    let shutdown = app_state.shutdown.clone();
+    app_state.shutdown.clone().spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let now = chrono::Utc::now().timestamp();
+            match app_state
+                .store
+                .blocking(move |store| store.delete_expired_sessions(now))
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => tracing::debug!("{} expired sessions are deleted", deleted),
+                Err(e) => tracing::warn!("failed to delete expired sessions: {}", e),
+            }
+            app_state.renew_locks.remove_unused();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renew_locks_are_per_session_and_removed_after_use() {
+        let locks = RenewLocks::default();
+        let a = locks.lock("a").await;
+        // another session is not blocked
+        let b = tokio::time::timeout(Duration::from_secs(1), locks.lock("b"))
+            .await
+            .expect("lock of another session should be acquired");
+        // the same session waits
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), locks.lock("a"))
+                .await
+                .is_err()
+        );
+        drop(a);
+        drop(b);
+        assert!(locks.locks.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn csrf_token_is_bound_to_session() {
+        assert_eq!(csrf_token("session"), csrf_token("session"));
+        assert_ne!(csrf_token("session"), csrf_token("other"));
+        // not to be the ID of the session in the database
+        assert_ne!(csrf_token("session"), hash_token("session"));
+    }
+}
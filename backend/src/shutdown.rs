@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFuture};
+use tokio_util::task::TaskTracker;
+
+/// Tracks driver operations and background tasks to wait for them on shutdown.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+    operations: Arc<Operations>,
+}
+
+#[derive(Default)]
+struct Operations {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, String>>,
+    finished: Notify,
+}
+
+/// Marks a driver operation as running until dropped.
+pub struct OperationGuard {
+    id: u64,
+    operations: Arc<Operations>,
+}
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.operations.running.lock().unwrap().remove(&self.id);
+        self.operations.finished.notify_waiters();
+    }
+}
+
+impl Shutdown {
+    /// Register a running driver operation, e.g. "start nas".
+    pub fn begin_operation(&self, description: String) -> OperationGuard {
+        let id = self.operations.next_id.fetch_add(1, Ordering::Relaxed);
+        self.operations
+            .running
+            .lock()
+            .unwrap()
+            .insert(id, description);
+        OperationGuard {
+            id,
+            operations: self.operations.clone(),
+        }
+    }
+
+    /// Spawn a background task. It should finish after `cancelled()` is completed.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Completed when shutdown is started.
+    pub fn cancelled(&self) -> WaitForCancellationFuture<'_> {
+        self.token.cancelled()
+    }
+
+    /// Start shutdown and wait for running operations and background tasks until `deadline`.
+    /// Operations still running at the deadline are logged.
+    pub async fn drain(&self, deadline: Instant) {
+        self.token.cancel();
+        self.tasks.close();
+
+        let pending = self.pending_operations();
+        if !pending.is_empty() {
+            tracing::info!("waiting for running operations: {:?}", pending);
+        }
+        let operations_finished = async {
+            loop {
+                // MEMO: register the waiter before checking, not to miss notifications
+                let finished = self.operations.finished.notified();
+                if self.operations.running.lock().unwrap().is_empty() {
+                    break;
+                }
+                finished.await;
+            }
+        };
+        let finished = tokio::time::timeout_at(deadline, async {
+            tokio::join!(operations_finished, self.tasks.wait());
+        })
+        .await;
+        if finished.is_err() {
+            tracing::warn!(
+                "shutdown timed out: running operations={:?}, background tasks={}",
+                self.pending_operations(),
+                self.tasks.len()
+            );
+        }
+    }
+
+    fn pending_operations(&self) -> Vec<String> {
+        self.operations
+            .running
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Completed when SIGTERM or SIGINT is received.
+pub async fn wait_for_signal() -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => tracing::info!("SIGTERM is received, shutting down"),
+        _ = sigint.recv() => tracing::info!("SIGINT is received, shutting down"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn drain_waits_for_operations_and_tasks() {
+        let shutdown = Shutdown::default();
+        let operation = shutdown.begin_operation("start nas".to_string());
+        let task_finished = Arc::new(AtomicBool::new(false));
+        let cancelled = shutdown.clone();
+        let finished = task_finished.clone();
+        shutdown.spawn(async move {
+            cancelled.cancelled().await;
+            finished.store(true, Ordering::SeqCst);
+        });
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(operation);
+        });
+
+        let started = Instant::now();
+        shutdown.drain(started + Duration::from_secs(5)).await;
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(shutdown.pending_operations().is_empty());
+        assert!(task_finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_at_deadline() {
+        let shutdown = Shutdown::default();
+        let _operation = shutdown.begin_operation("stop nas".to_string());
+        // a task ignoring the cancellation
+        shutdown.spawn(tokio::time::sleep(Duration::from_secs(60)));
+
+        let started = Instant::now();
+        shutdown.drain(started + Duration::from_millis(100)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(shutdown.pending_operations(), ["stop nas"]);
+    }
+}
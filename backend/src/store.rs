@@ -0,0 +1,561 @@
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::cmd::{BoxError, StorageConfig};
+use crate::Error;
+
+// Schema migrations applied in order. The number of applied ones is kept in `PRAGMA user_version`,
+// so append new migrations to the end and never modify the released ones.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_create_operations.sql"),
+    include_str!("../migrations/0002_create_sessions.sql"),
+    include_str!("../migrations/0003_add_refresh_token_to_sessions.sql"),
+    include_str!("../migrations/0004_create_api_tokens.sql"),
+    include_str!("../migrations/0005_add_provider.sql"),
+];
+
+/// Embedded SQLite database keeping the launcher data across restarts.
+/// Its methods block the thread, so call them in `spawn_blocking` from async code.
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    /// Open the database file of `config`, or an in-memory database if it is not set,
+    /// and apply pending migrations.
+    pub fn open(config: &StorageConfig) -> Result<Self, BoxError> {
+        let mut conn = match &config.path {
+            Some(path) => {
+                tracing::info!("opening database {}", path);
+                // MEMO: created readable only by the owner, because it contains ID tokens and
+                // refresh tokens in plaintext. SQLite creates the WAL files with the same mode.
+                if let Err(e) = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(path)
+                {
+                    if e.kind() != std::io::ErrorKind::AlreadyExists {
+                        return Err(e.into());
+                    }
+                }
+                Connection::open(Path::new(path))?
+            }
+            None => {
+                tracing::warn!("storage.path is not set, data is kept in memory and lost on exit");
+                Connection::open_in_memory()?
+            }
+        };
+        // MEMO: WAL allows other processes (e.g. `sqlite3` for inspection or backups) to read the database while the server is writing.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        migrate(&mut conn)?;
+
+        Ok(Store {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Run `f` on a blocking thread, to call methods from async handlers.
+    pub async fn blocking<T, F>(self: &Arc<Self>, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Store) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store))
+            .await
+            .map_err(|e| Error::InternalServerError(format!("database task failed: {}", e).into()))?
+            .map_err(Error::from)
+    }
+
+    // Lock the connection. Queries are provided as methods of `Store`.
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap()
+    }
+
+    /// Record the result of a power operation.
+    pub fn record_operation(&self, operation: &OperationRecord) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "INSERT INTO operations (machine, action, succeeded, error, started_at, finished_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                operation.machine,
+                operation.action,
+                operation.error.is_none(),
+                operation.error,
+                operation.started_at,
+                operation.finished_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_session(&self, session: &SessionRecord) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "INSERT INTO sessions
+             (id, provider, claims, id_token, refresh_token, created_at, expires_at, max_expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                session.id,
+                session.provider,
+                session.claims.to_string(),
+                session.id_token,
+                session.refresh_token,
+                session.created_at,
+                session.expires_at,
+                session.max_expires_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Update the tokens and the expiry of the renewed session.
+    pub fn update_session(&self, session: &SessionRecord) -> Result<(), rusqlite::Error> {
+        self.conn().execute(
+            "UPDATE sessions SET claims = ?2, id_token = ?3, refresh_token = ?4, expires_at = ?5
+             WHERE id = ?1",
+            params![
+                session.id,
+                session.claims.to_string(),
+                session.id_token,
+                session.refresh_token,
+                session.expires_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Find the session of `id` which has not expired or can be renewed at `now`.
+    pub fn find_session(
+        &self,
+        id: &str,
+        now: i64,
+    ) -> Result<Option<SessionRecord>, rusqlite::Error> {
+        self.conn()
+            .query_row(
+                "SELECT id, provider, claims, id_token, refresh_token, created_at, expires_at,
+                        max_expires_at
+                 FROM sessions
+                 WHERE id = ?1
+                   AND (expires_at > ?2 OR (refresh_token IS NOT NULL AND max_expires_at > ?2))",
+                params![id, now],
+                session_from_row,
+            )
+            .optional()
+    }
+
+    /// Delete the session of `id` and return it even if it has expired.
+    pub fn take_session(&self, id: &str) -> Result<Option<SessionRecord>, rusqlite::Error> {
+        self.conn()
+            .query_row(
+                "DELETE FROM sessions WHERE id = ?1
+                 RETURNING id, provider, claims, id_token, refresh_token, created_at, expires_at,
+                           max_expires_at",
+                params![id],
+                session_from_row,
+            )
+            .optional()
+    }
+
+    /// Delete sessions which are expired and cannot be renewed at `now`,
+    /// and return the number of them.
+    pub fn delete_expired_sessions(&self, now: i64) -> Result<usize, rusqlite::Error> {
+        self.conn().execute(
+            "DELETE FROM sessions
+             WHERE expires_at <= ?1 AND (refresh_token IS NULL OR max_expires_at <= ?1)",
+            params![now],
+        )
+    }
+
+    /// Insert the API token and return its ID, or `None` if the owner has `max_per_owner` tokens.
+    /// Fails if the owner has a token of the same name.
+    pub fn insert_api_token(
+        &self,
+        token: &ApiTokenRecord,
+        max_per_owner: usize,
+    ) -> Result<Option<i64>, rusqlite::Error> {
+        let mut conn = self.conn();
+        // MEMO: count and insert in a transaction, not to exceed the limit by parallel requests.
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let count: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM api_tokens WHERE provider = ?1 AND owner = ?2",
+            params![token.provider, token.owner],
+            |row| row.get(0),
+        )?;
+        if usize::try_from(count).unwrap_or(usize::MAX) >= max_per_owner {
+            return Ok(None);
+        }
+        tx.execute(
+            "INSERT INTO api_tokens
+             (token_hash, provider, owner, name, claims, claims_updated_at, read_only, machines,
+              created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                token.token_hash,
+                token.provider,
+                token.owner,
+                token.name,
+                token.claims.to_string(),
+                token.claims_updated_at,
+                token.read_only,
+                token
+                    .machines
+                    .as_ref()
+                    .map(|m| serde_json::to_string(m).unwrap_or_default()),
+                token.created_at,
+                token.expires_at,
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.commit()?;
+        Ok(Some(id))
+    }
+
+    /// Replace the claims of API tokens of `owner` of `provider` with the latest ones got at
+    /// `now`, and return the number of them.
+    pub fn update_api_token_claims(
+        &self,
+        provider: &str,
+        owner: &str,
+        claims: &serde_json::Value,
+        now: i64,
+    ) -> Result<usize, rusqlite::Error> {
+        self.conn().execute(
+            "UPDATE api_tokens SET claims = ?3, claims_updated_at = ?4
+             WHERE provider = ?1 AND owner = ?2",
+            params![provider, owner, claims.to_string(), now],
+        )
+    }
+
+    /// List API tokens of `owner` of `provider` in the order of creation, including expired ones.
+    pub fn list_api_tokens(
+        &self,
+        provider: &str,
+        owner: &str,
+    ) -> Result<Vec<ApiTokenRecord>, rusqlite::Error> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, token_hash, provider, owner, name, claims, claims_updated_at, read_only,
+                    machines, created_at, expires_at, last_used_at
+             FROM api_tokens WHERE provider = ?1 AND owner = ?2 ORDER BY id",
+        )?;
+        let tokens = stmt
+            .query_map(params![provider, owner], api_token_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tokens)
+    }
+
+    /// Find the API token of `token_hash` which has not expired at `now` and whose claims are
+    /// updated after `claims_updated_after`, and record its use.
+    pub fn use_api_token(
+        &self,
+        token_hash: &str,
+        now: i64,
+        claims_updated_after: i64,
+    ) -> Result<Option<ApiTokenRecord>, rusqlite::Error> {
+        self.conn()
+            .query_row(
+                "UPDATE api_tokens SET last_used_at = ?2
+                 WHERE token_hash = ?1 AND expires_at > ?2 AND claims_updated_at > ?3
+                 RETURNING id, token_hash, provider, owner, name, claims, claims_updated_at,
+                           read_only, machines, created_at, expires_at, last_used_at",
+                params![token_hash, now, claims_updated_after],
+                api_token_from_row,
+            )
+            .optional()
+    }
+
+    /// Delete the API token of `id` owned by `owner` of `provider`, and return whether it existed.
+    pub fn delete_api_token(
+        &self,
+        id: i64,
+        provider: &str,
+        owner: &str,
+    ) -> Result<bool, rusqlite::Error> {
+        let deleted = self.conn().execute(
+            "DELETE FROM api_tokens WHERE id = ?1 AND provider = ?2 AND owner = ?3",
+            params![id, provider, owner],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Delete API tokens which are expired at `now`, and return the number of them.
+    pub fn delete_expired_api_tokens(&self, now: i64) -> Result<usize, rusqlite::Error> {
+        self.conn().execute(
+            "DELETE FROM api_tokens WHERE expires_at <= ?1",
+            params![now],
+        )
+    }
+}
+
+/// A row of the operation history. Timestamps are UNIX time in seconds.
+#[derive(Debug, Clone)]
+pub struct OperationRecord {
+    pub machine: String,
+    pub action: String,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub finished_at: i64,
+}
+
+/// A row of the login sessions. Timestamps are UNIX time in seconds.
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    // SHA-256 hash of the session token
+    pub id: String,
+    // Name of the OIDC provider the user logged in with
+    pub provider: String,
+    // Claims of the ID token verified on login
+    pub claims: serde_json::Value,
+    pub id_token: String,
+    // Refresh token to renew the session, if the OIDC provider issued it
+    pub refresh_token: Option<String>,
+    pub created_at: i64,
+    // When the ID token expires. It is extended by renewals up to `max_expires_at`.
+    pub expires_at: i64,
+    pub max_expires_at: i64,
+}
+
+fn session_from_row(row: &rusqlite::Row) -> Result<SessionRecord, rusqlite::Error> {
+    Ok(SessionRecord {
+        id: row.get(0)?,
+        provider: row.get(1)?,
+        claims: from_json_column(2, &row.get::<_, String>(2)?)?,
+        id_token: row.get(3)?,
+        refresh_token: row.get(4)?,
+        created_at: row.get(5)?,
+        expires_at: row.get(6)?,
+        max_expires_at: row.get(7)?,
+    })
+}
+
+/// A row of the personal API tokens. Timestamps are UNIX time in seconds.
+#[derive(Debug, Clone)]
+pub struct ApiTokenRecord {
+    pub id: i64,
+    // SHA-256 hash of the token
+    pub token_hash: String,
+    // Name of the OIDC provider of the owner
+    pub provider: String,
+    // `sub` claim of the user who created the token
+    pub owner: String,
+    pub name: String,
+    // Claims of the owner on the last login, used for authorization
+    pub claims: serde_json::Value,
+    pub claims_updated_at: i64,
+    // Only viewing machines is allowed
+    pub read_only: bool,
+    // Machines the token is limited to. `None` means all machines the owner may use.
+    pub machines: Option<Vec<String>>,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+fn api_token_from_row(row: &rusqlite::Row) -> Result<ApiTokenRecord, rusqlite::Error> {
+    let machines: Option<String> = row.get(8)?;
+    Ok(ApiTokenRecord {
+        id: row.get(0)?,
+        token_hash: row.get(1)?,
+        provider: row.get(2)?,
+        owner: row.get(3)?,
+        name: row.get(4)?,
+        claims: from_json_column(5, &row.get::<_, String>(5)?)?,
+        claims_updated_at: row.get(6)?,
+        read_only: row.get(7)?,
+        machines: machines.map(|m| from_json_column(8, &m)).transpose()?,
+        created_at: row.get(9)?,
+        expires_at: row.get(10)?,
+        last_used_at: row.get(11)?,
+    })
+}
+
+fn from_json_column<T: serde::de::DeserializeOwned>(
+    i: usize,
+    s: &str,
+) -> Result<T, rusqlite::Error> {
+    serde_json::from_str(s).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), BoxError> {
+    let applied: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if applied > MIGRATIONS.len() {
+        // MEMO: refuse to touch the database created by the newer version
+        return Err(format!(
+            "database schema version {} is newer than supported version {}",
+            applied,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        tracing::info!("database migration {} is applied", i + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrate_creates_fresh_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        // applied only once
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrate_keeps_data_of_older_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // the database of the version with sessions and API tokens of the `[oidc]` provider only
+        for migration in &MIGRATIONS[..4] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 4).unwrap();
+        conn.execute_batch(
+            "INSERT INTO sessions (id, claims, id_token, created_at, expires_at)
+             VALUES ('s1', '{}', 'idt', 0, 10);
+             INSERT INTO api_tokens (token_hash, owner, name, claims, claims_updated_at,
+                                     read_only, created_at, expires_at)
+             VALUES ('h1', 'user1', 'ci', '{}', 0, 1, 0, 10);",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let session: (String, Option<String>) = conn
+            .query_row(
+                "SELECT provider, refresh_token FROM sessions WHERE id = 's1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(session, ("default".to_string(), None));
+        let token: (String, String) = conn
+            .query_row(
+                "SELECT provider, owner FROM api_tokens WHERE token_hash = 'h1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(token, ("default".to_string(), "user1".to_string()));
+    }
+
+    #[test]
+    fn migrate_refuses_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+
+    #[test]
+    fn open_creates_database_readable_only_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!(
+            "machine-launcher-store-{}.sqlite",
+            std::process::id()
+        ));
+        let config = StorageConfig {
+            path: Some(path.to_string_lossy().into_owned()),
+        };
+        drop(Store::open(&config).unwrap());
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    fn token(owner: &str, name: &str) -> ApiTokenRecord {
+        ApiTokenRecord {
+            id: 0,
+            token_hash: format!("hash-{}-{}", owner, name),
+            provider: "default".to_string(),
+            owner: owner.to_string(),
+            name: name.to_string(),
+            claims: serde_json::json!({"sub": owner, "roles": ["admin"]}),
+            claims_updated_at: 0,
+            read_only: false,
+            machines: None,
+            created_at: 0,
+            expires_at: i64::MAX,
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn insert_api_token_is_limited_per_owner() {
+        let store = Store::open(&StorageConfig { path: None }).unwrap();
+        assert!(store
+            .insert_api_token(&token("a", "1"), 2)
+            .unwrap()
+            .is_some());
+        assert!(store
+            .insert_api_token(&token("a", "2"), 2)
+            .unwrap()
+            .is_some());
+        assert!(store
+            .insert_api_token(&token("a", "3"), 2)
+            .unwrap()
+            .is_none());
+        assert!(store
+            .insert_api_token(&token("b", "1"), 2)
+            .unwrap()
+            .is_some());
+        assert!(store.insert_api_token(&token("b", "1"), 2).is_err());
+        assert_eq!(store.list_api_tokens("default", "a").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn update_api_token_claims_replaces_claims_of_owner() {
+        let store = Store::open(&StorageConfig { path: None }).unwrap();
+        store.insert_api_token(&token("a", "1"), 10).unwrap();
+        store.insert_api_token(&token("b", "1"), 10).unwrap();
+        let claims = serde_json::json!({"sub": "a", "roles": []});
+        assert_eq!(
+            store
+                .update_api_token_claims("default", "a", &claims, 10)
+                .unwrap(),
+            1
+        );
+        let found = store.use_api_token("hash-a-1", 0, -1).unwrap().unwrap();
+        assert_eq!(found.claims, claims);
+        assert_eq!(found.claims_updated_at, 10);
+        let other = store.use_api_token("hash-b-1", 0, -1).unwrap().unwrap();
+        assert_eq!(other.claims["roles"], serde_json::json!(["admin"]));
+    }
+
+    #[test]
+    fn use_api_token_refuses_stale_claims() {
+        let store = Store::open(&StorageConfig { path: None }).unwrap();
+        store.insert_api_token(&token("a", "1"), 10).unwrap();
+        assert!(store.use_api_token("hash-a-1", 0, 0).unwrap().is_none());
+        let claims = serde_json::json!({"sub": "a"});
+        store
+            .update_api_token_claims("default", "a", &claims, 10)
+            .unwrap();
+        assert!(store.use_api_token("hash-a-1", 0, 0).unwrap().is_some());
+    }
+}
@@ -0,0 +1,286 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use machine_launcher_utils::{format_machine_table, MachineRow};
+use openapi::apis::{app_api, configuration::Configuration};
+use openapi::models::{server::Status, ErrorMessage, Server, ServerName};
+use tokio::time::Instant;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// Exit codes other than success, so that scripts can branch on the result
+// MEMO: machines whose status is unknown exit with EXIT_ERROR, not to be taken as stopped.
+const EXIT_ERROR: u8 = 1;
+const EXIT_STOPPED: u8 = 3;
+const EXIT_TIMEOUT: u8 = 4;
+
+// Environment variable holding the token, not to pass it by arguments visible in `ps`
+const TOKEN_ENV: &str = "MACHINE_LAUNCHER_TOKEN";
+
+#[derive(Parser)]
+#[command(version, about = "Command line client of machine-launcher API")]
+struct Args {
+    /// URL of machine-launcher, e.g. https://launcher.example.com
+    #[arg(long, env = "MACHINE_LAUNCHER_URL")]
+    url: String,
+
+    /// File containing the token for API. If not set, $MACHINE_LAUNCHER_TOKEN is used
+    #[arg(long, env = "MACHINE_LAUNCHER_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all machines with their status
+    List,
+    /// Show the status of the machine. Exit with 3 if it is stopped, 1 if it is unknown
+    Status {
+        /// Machine name
+        name: String,
+    },
+    /// Start the machine
+    Start {
+        /// Machine name
+        name: String,
+        /// Wait until the machine is running
+        #[arg(long)]
+        wait: bool,
+        #[command(flatten)]
+        wait_options: WaitOptions,
+    },
+    /// Stop the machine
+    Stop {
+        /// Machine name
+        name: String,
+        /// Wait until the machine is stopped
+        #[arg(long)]
+        wait: bool,
+        #[command(flatten)]
+        wait_options: WaitOptions,
+    },
+    /// Wait until the machine becomes the state. Exit with 4 on timeout
+    Wait {
+        /// Machine name
+        name: String,
+        /// State to wait for
+        #[arg(long = "for", value_enum, default_value_t = State::Running)]
+        state: State,
+        #[command(flatten)]
+        wait_options: WaitOptions,
+    },
+}
+
+#[derive(ClapArgs)]
+struct WaitOptions {
+    /// Seconds to wait for the state
+    #[arg(long, default_value_t = 300)]
+    timeout: u64,
+    /// Seconds between status checks
+    #[arg(long, default_value_t = 5)]
+    interval: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum State {
+    Running,
+    Stopped,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<ExitCode, BoxError> {
+    let configuration = Configuration {
+        base_path: args.url.trim_end_matches('/').to_string(),
+        user_agent: Some(format!(
+            "machine-launcher-cli/{}",
+            env!("CARGO_PKG_VERSION")
+        )),
+        bearer_access_token: Some(load_token(args.token_file.as_ref())?),
+        ..Default::default()
+    };
+    let output = args.output;
+
+    match args.command {
+        Command::List => {
+            let servers = app_api::list_servers(&configuration)
+                .await
+                .map_err(api_error)?;
+            match output {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&servers)?),
+                OutputFormat::Table => print_table(&servers),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Status { name } => {
+            let server = get_server(&configuration, &name).await?;
+            print_server(output, &server)?;
+            Ok(exit_code_of(&server))
+        }
+        Command::Start {
+            name,
+            wait,
+            wait_options,
+        } => {
+            let server = app_api::start_server(&configuration, ServerName::new(name.clone()))
+                .await
+                .map_err(api_error)?;
+            if wait {
+                return wait_for(&configuration, output, &name, State::Running, &wait_options)
+                    .await;
+            }
+            print_server(output, &server)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Stop {
+            name,
+            wait,
+            wait_options,
+        } => {
+            let server = app_api::stop_server(&configuration, ServerName::new(name.clone()))
+                .await
+                .map_err(api_error)?;
+            if wait {
+                return wait_for(&configuration, output, &name, State::Stopped, &wait_options)
+                    .await;
+            }
+            print_server(output, &server)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Wait {
+            name,
+            state,
+            wait_options,
+        } => wait_for(&configuration, output, &name, state, &wait_options).await,
+    }
+}
+
+// Read the token from `token_file` if set, otherwise from the environment variable.
+fn load_token(token_file: Option<&PathBuf>) -> Result<String, BoxError> {
+    let token = match token_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read token file {}: {}", path.display(), e))?,
+        None => std::env::var(TOKEN_ENV)
+            .map_err(|_| format!("token is required: set ${} or --token-file", TOKEN_ENV))?,
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("token is empty".into());
+    }
+    Ok(token.to_string())
+}
+
+async fn get_server(configuration: &Configuration, name: &str) -> Result<Server, BoxError> {
+    app_api::list_servers(configuration)
+        .await
+        .map_err(api_error)?
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or_else(|| format!("machine {:?} is not found", name).into())
+}
+
+// Poll the status until the machine becomes `state`, and print the last status.
+async fn wait_for(
+    configuration: &Configuration,
+    output: OutputFormat,
+    name: &str,
+    state: State,
+    options: &WaitOptions,
+) -> Result<ExitCode, BoxError> {
+    let deadline = Instant::now() + Duration::from_secs(options.timeout);
+    loop {
+        let server = get_server(configuration, name).await?;
+        // MEMO: unknown status (e.g. the BMC is unreachable) is polled again until the timeout.
+        let reached = match state {
+            State::Running => server.status == Status::Running,
+            State::Stopped => server.status == Status::Stopped,
+        };
+        if reached {
+            print_server(output, &server)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        if Instant::now() >= deadline {
+            print_server(output, &server)?;
+            eprintln!("error: timed out waiting for machine {:?}", name);
+            return Ok(ExitCode::from(EXIT_TIMEOUT));
+        }
+        tokio::time::sleep_until(
+            deadline.min(Instant::now() + Duration::from_secs(options.interval)),
+        )
+        .await;
+    }
+}
+
+fn exit_code_of(server: &Server) -> ExitCode {
+    match server.status {
+        Status::Running => ExitCode::SUCCESS,
+        Status::Stopped => ExitCode::from(EXIT_STOPPED),
+        Status::Unknown => ExitCode::from(EXIT_ERROR),
+    }
+}
+
+fn status_str(status: Status) -> &'static str {
+    match status {
+        Status::Running => "running",
+        Status::Stopped => "stopped",
+        Status::Unknown => "unknown",
+    }
+}
+
+// Use the message of the error response body if exists.
+fn api_error<T>(e: openapi::apis::Error<T>) -> BoxError {
+    match e {
+        openapi::apis::Error::ResponseError(res) => {
+            let message = serde_json::from_str::<ErrorMessage>(&res.content)
+                .map(|m| m.error)
+                .unwrap_or(res.content);
+            format!("{}: {}", res.status, message).into()
+        }
+        e => e.to_string().into(),
+    }
+}
+
+fn print_server(output: OutputFormat, server: &Server) -> Result<(), BoxError> {
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(server)?),
+        OutputFormat::Table => print_table(std::slice::from_ref(server)),
+    }
+    Ok(())
+}
+
+fn print_table(servers: &[Server]) {
+    print!(
+        "{}",
+        format_machine_table(servers.iter().map(|s| MachineRow {
+            name: &s.name,
+            hostname: &s.hostname,
+            status: status_str(s.status),
+            reason: s.reason.as_deref(),
+        }))
+    );
+}
@@ -91,5 +91,7 @@ pub fn parse_deep_object(prefix: &str, value: &serde_json::Value) -> Vec<(String
 }
 
 pub mod app_api;
+pub mod auth_api;
+pub mod tokens_api;
 
 pub mod configuration;
@@ -0,0 +1,45 @@
+/*
+ * machine-launcher
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: v0.1.0
+ * 
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    #[serde(rename = "id")]
+    pub id: i64,
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "read_only")]
+    pub read_only: bool,
+    #[serde(rename = "machines", skip_serializing_if = "Option::is_none")]
+    pub machines: Option<Vec<String>>,
+    #[serde(rename = "created_at")]
+    pub created_at: String,
+    #[serde(rename = "expires_at")]
+    pub expires_at: String,
+    #[serde(rename = "last_used_at", skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<String>,
+}
+
+impl ApiToken {
+    pub fn new(id: i64, name: String, read_only: bool, created_at: String, expires_at: String) -> ApiToken {
+        ApiToken {
+            id,
+            name,
+            read_only,
+            machines: None,
+            created_at,
+            expires_at,
+            last_used_at: None,
+        }
+    }
+}
+
@@ -1,6 +1,18 @@
+pub mod api_token;
+pub use self::api_token::ApiToken;
+pub mod created_api_token;
+pub use self::created_api_token::CreatedApiToken;
 pub mod error_message;
 pub use self::error_message::ErrorMessage;
+pub mod new_api_token;
+pub use self::new_api_token::NewApiToken;
+pub mod provider;
+pub use self::provider::Provider;
 pub mod server;
 pub use self::server::Server;
+pub mod server_link;
+pub use self::server_link::ServerLink;
 pub mod server_name;
 pub use self::server_name::ServerName;
+pub mod user;
+pub use self::user::User;
@@ -0,0 +1,38 @@
+/*
+ * machine-launcher
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: v0.1.0
+ * 
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NewApiToken {
+    #[serde(rename = "name")]
+    pub name: String,
+    /// Only viewing servers is allowed
+    #[serde(rename = "read_only", skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// Servers the token is limited to. If not set, all servers are allowed
+    #[serde(rename = "machines", skip_serializing_if = "Option::is_none")]
+    pub machines: Option<Vec<String>>,
+    #[serde(rename = "expires_in_secs")]
+    pub expires_in_secs: i64,
+}
+
+impl NewApiToken {
+    pub fn new(name: String, expires_in_secs: i64) -> NewApiToken {
+        NewApiToken {
+            name,
+            read_only: None,
+            machines: None,
+            expires_in_secs,
+        }
+    }
+}
+
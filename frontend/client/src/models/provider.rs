@@ -0,0 +1,49 @@
+/*
+ * machine-launcher
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: v0.1.0
+ * 
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Provider {
+    /// Name used in /auth/login/{name}
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "display_name")]
+    pub display_name: String,
+    /// oidc is logged in at /auth/login/{name}, and local by posting username and password to /auth/local/login
+    #[serde(rename = "kind")]
+    pub kind: Kind,
+}
+
+impl Provider {
+    pub fn new(name: String, display_name: String, kind: Kind) -> Provider {
+        Provider {
+            name,
+            display_name,
+            kind,
+        }
+    }
+}
+/// oidc is logged in at /auth/login/{name}, and local by posting username and password to /auth/local/login
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Kind {
+    #[serde(rename = "oidc")]
+    Oidc,
+    #[serde(rename = "local")]
+    Local,
+}
+
+impl Default for Kind {
+    fn default() -> Kind {
+        Self::Oidc
+    }
+}
+
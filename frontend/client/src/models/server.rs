@@ -15,22 +15,76 @@ use serde::{Deserialize, Serialize};
 pub struct Server {
     #[serde(rename = "name")]
     pub name: String,
+    #[serde(rename = "display_name", skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
     #[serde(rename = "hostname")]
     pub hostname: String,
+    #[serde(rename = "description", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "tags")]
+    pub tags: Vec<String>,
+    #[serde(rename = "icon", skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(rename = "links")]
+    pub links: Vec<models::ServerLink>,
+    /// Whether the server is running. It is false also if the status is unknown
     #[serde(rename = "running")]
     pub running: bool,
+    /// Power status of the server. It is unknown if the driver failed to get it
+    #[serde(rename = "status")]
+    pub status: Status,
     #[serde(rename = "reason", skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
+    /// Actions the user may do on the server
+    #[serde(rename = "actions")]
+    pub actions: Vec<Actions>,
 }
 
 impl Server {
-    pub fn new(name: String, hostname: String, running: bool) -> Server {
+    pub fn new(name: String, hostname: String, tags: Vec<String>, links: Vec<models::ServerLink>, running: bool, status: Status, actions: Vec<Actions>) -> Server {
         Server {
             name,
+            display_name: None,
             hostname,
+            description: None,
+            tags,
+            icon: None,
+            links,
             running,
+            status,
             reason: None,
+            actions,
         }
     }
 }
+/// Power status of the server. It is unknown if the driver failed to get it
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Status {
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "stopped")]
+    Stopped,
+    #[serde(rename = "unknown")]
+    Unknown,
+}
+
+impl Default for Status {
+    fn default() -> Status {
+        Self::Running
+    }
+}
+/// Actions the user may do on the server
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Actions {
+    #[serde(rename = "start")]
+    Start,
+    #[serde(rename = "stop")]
+    Stop,
+}
+
+impl Default for Actions {
+    fn default() -> Actions {
+        Self::Start
+    }
+}
 
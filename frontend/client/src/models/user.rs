@@ -0,0 +1,40 @@
+/*
+ * machine-launcher
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: v0.1.0
+ * 
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    #[serde(rename = "sub")]
+    pub sub: String,
+    #[serde(rename = "name", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "email", skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(rename = "picture", skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    /// Token to send in X-CSRF-Token header on changes by the session cookie
+    #[serde(rename = "csrf_token", skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
+}
+
+impl User {
+    pub fn new(sub: String) -> User {
+        User {
+            sub,
+            name: None,
+            email: None,
+            picture: None,
+            csrf_token: None,
+        }
+    }
+}
+
@@ -2,3 +2,4 @@ pub mod contents;
 pub mod footer;
 pub mod header;
 pub mod server;
+pub mod tokens;
@@ -1,6 +1,12 @@
+use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
 
+use openapi::apis::auth_api::list_providers;
+use openapi::models::provider::Kind;
+use openapi::models::Provider;
+
 use super::server::Server as ServerItem;
+use crate::state::{configuration, login_error, login_url, return_to};
 
 #[derive(PartialEq, Properties)]
 pub struct ContentsProps {
@@ -15,18 +21,73 @@ pub fn Contents(props: &ContentsProps) -> Html {
     } else {
         ""
     };
+    let providers = use_state(|| vec![] as Vec<Provider>);
+    {
+        let providers = providers.clone();
+        let logged_in = props.user.is_some();
+        use_effect_with(logged_in, move |logged_in| {
+            if !*logged_in {
+                spawn_local(async move {
+                    if let Ok(res) = list_providers(&configuration()).await {
+                        providers.set(res);
+                    }
+                });
+            }
+        });
+    }
 
     if props.user.is_none() {
+        // one button per OIDC provider, or the one of `/auth/login` until providers are fetched
+        let oidc_providers: Vec<&Provider> =
+            providers.iter().filter(|p| p.kind == Kind::Oidc).collect();
+        let buttons: Vec<(String, String)> = match oidc_providers.as_slice() {
+            [] if providers.is_empty() => vec![(
+                login_url("/auth/login"),
+                "Please login with OIDC Provider".to_string(),
+            )],
+            [provider] => vec![(
+                login_url(&format!("/auth/login/{}", provider.name)),
+                "Please login with OIDC Provider".to_string(),
+            )],
+            _ => oidc_providers
+                .iter()
+                .map(|p| {
+                    (
+                        login_url(&format!("/auth/login/{}", p.name)),
+                        format!("Login with {}", p.display_name),
+                    )
+                })
+                .collect(),
+        };
+        let local_provider = providers.iter().find(|p| p.kind == Kind::Local).cloned();
         html! {
-        <div>
-            <a href="/auth/login">
+        <div class="flex flex-col gap-3">
+            if let Some(message) = login_error() {
+            <p class="text-sm text-red-600">{message}</p>
+            }
+            { for buttons.into_iter().map(|(href, label)| html! {
+            <a href={href}>
                 <button type="button" class="text-white bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 font-medium rounded-lg text-sm px-5 py-2.5 text-center inline-flex items-center dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800">
-                    {"Please login with OIDC Provider"}
+                    {label}
                     <svg class="rtl:rotate-180 w-3.5 h-3.5 ms-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 14 10">
                         <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M1 5h12m0 0L9 1m4 4L9 9"/>
                     </svg>
                 </button>
             </a>
+            })}
+            if let Some(local_provider) = local_provider {
+            <form method="post" action="/auth/local/login" class="flex flex-col gap-2 p-4 bg-white border border-gray-200 rounded-lg shadow-sm">
+                <span class="text-sm font-medium text-gray-900">{local_provider.display_name}</span>
+                <input class="border border-gray-300 rounded px-2 py-1 text-sm" type="text" name="username"
+                    placeholder="Username" autocomplete="username" required=true />
+                <input class="border border-gray-300 rounded px-2 py-1 text-sm" type="password" name="password"
+                    placeholder="Password" autocomplete="current-password" required=true />
+                <input type="hidden" name="return_to" value={return_to()} />
+                <button type="submit" class="text-white bg-gray-700 hover:bg-gray-800 font-medium rounded-lg text-sm px-5 py-2.5">
+                    {"Login"}
+                </button>
+            </form>
+            }
         </div>
             }
     } else {
@@ -1,5 +1,7 @@
 use yew::prelude::*;
 
+use crate::state::{csrf_token, login_url};
+
 #[derive(PartialEq, Properties)]
 pub struct HeaderProps {
     pub user: Option<crate::state::Userinfo>,
@@ -24,11 +26,9 @@ pub fn Header(props: &HeaderProps) -> Html {
 pub fn HeaderLogin(props: &HeaderProps) -> Html {
     let is_open = use_state(|| false);
     let hidden_class = if *is_open { "" } else { "hidden" };
-    let icon_url = if props.user.is_some() {
-        let url = props.user.clone().unwrap().icon_url;
-        url[1..url.len() - 1].to_string()
-    } else {
-        String::from("/public/default-avator.svg")
+    let icon_url = match &props.user {
+        Some(user) if !user.icon_url.is_empty() => user.icon_url.clone(),
+        _ => String::from("/public/default-avator.svg"),
     };
     let usermenu_toggle = {
         let is_open = is_open.clone();
@@ -39,7 +39,7 @@ pub fn HeaderLogin(props: &HeaderProps) -> Html {
         html! {
             <div class="relative pr-4">
                 <button onclick={usermenu_toggle} class="flex items-center focus:outline-none">
-                    <a href="/auth/login">{"LOGIN"}</a>
+                    <a href={login_url("/auth/login")}>{"LOGIN"}</a>
                 </button>
             </div>
         }
@@ -59,11 +59,23 @@ pub fn HeaderLogin(props: &HeaderProps) -> Html {
                     "absolute", "right-0", "mt-2", "w-48", "bg-white",
                     "rounded-md", "shadow-lg", "py-1", "z-10", hidden_class,
                 )}>
-                <a href="/auth/logout">
+                <a href="#/">
                     <button class="block px-4 py-2 text-sm text-gray-700 hover:bg-gray-100 w-full text-left inline-block mr-2" size={16} >
-                        {"Logout"}
+                        {"Machines"}
+                    </button>
+                </a>
+                <a href="#/tokens">
+                    <button class="block px-4 py-2 text-sm text-gray-700 hover:bg-gray-100 w-full text-left inline-block mr-2" size={16} >
+                        {"API tokens"}
                     </button>
                 </a>
+                // MEMO: logout is POST with the CSRF token, not to be done by links on other sites
+                <form method="post" action="/auth/logout">
+                    <input type="hidden" name="csrf_token" value={csrf_token().unwrap_or_default()} />
+                    <button type="submit" class="block px-4 py-2 text-sm text-gray-700 hover:bg-gray-100 w-full text-left inline-block mr-2" size={16} >
+                        {"Logout"}
+                    </button>
+                </form>
                 </div>
             </div>
 
@@ -1,10 +1,13 @@
-use gloo::utils::window;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
 
 use openapi::apis::app_api::{start_server, stop_server};
-use openapi::apis::configuration::Configuration;
-use openapi::models::ServerName;
+use openapi::models::{
+    server::{Actions, Status},
+    ServerName,
+};
+
+use crate::state::configuration;
 
 #[derive(PartialEq, Properties)]
 pub struct ServerProps {
@@ -14,11 +17,29 @@ pub struct ServerProps {
 #[function_component]
 pub fn Server(props: &ServerProps) -> Html {
     let is_open = use_state(|| false);
-    let server_status_color = if props.server.running {
-        "#27C940"
-    } else {
-        "#FF5F59"
+    let server_status_color = match props.server.status {
+        Status::Running => "#27C940",
+        Status::Stopped => "#FF5F59",
+        Status::Unknown => "#9CA3AF",
     };
+    let display_name = props
+        .server
+        .display_name
+        .clone()
+        .unwrap_or_else(|| props.server.name.clone());
+    let icon = props
+        .server
+        .icon
+        .clone()
+        .unwrap_or_else(|| String::from("/public/server.png"));
+    // the button is shown only if the user may do the next action, and not while the status
+    // is unknown, not to start machines which may be running
+    let next_action = match props.server.status {
+        Status::Running => Some(Actions::Stop),
+        Status::Stopped => Some(Actions::Start),
+        Status::Unknown => None,
+    };
+    let can_switch = next_action.is_some_and(|action| props.server.actions.contains(&action));
 
     html! {
         <div class="basis-1/6 w-full max-y-sm max-w-sm bg-white border border-gray-200 rounded-lg shadow-sm dark:bg-gray-800 dark:border-gray-700 mx-4">
@@ -29,15 +50,35 @@ pub fn Server(props: &ServerProps) -> Html {
             </div>
 
             <div class="flex flex-col items-center">
-                <img class="w-24 h-24 mb-3 rounded-full shadow-lg" src="/public/server.png" alt="Server image"/>
-                <h5 class="p-4 mb-1 text-xl font-medium text-gray-900 dark:text-white">{props.server.name.clone()}</h5>
+                <img class="w-24 h-24 mb-3 rounded-full shadow-lg" src={icon} alt="Server image"/>
+                <h5 class="p-4 mb-1 text-xl font-medium text-gray-900 dark:text-white">{display_name}</h5>
                 <span class="text-sm text-gray-500 dark:text-gray-400">{props.server.hostname.clone()}</span>
+                if let Some(description) = props.server.description.clone() {
+                    <p class="px-4 pt-2 text-sm text-center text-gray-700 dark:text-gray-300">{description}</p>
+                }
+                <div class="flex flex-wrap justify-center gap-1 px-4 pt-2">
+                    { for props.server.tags.iter().map(|tag| html! {
+                        <span class="bg-gray-100 text-gray-800 text-xs font-medium px-2.5 py-0.5 rounded-sm dark:bg-gray-700 dark:text-gray-300">{tag.clone()}</span>
+                    })}
+                </div>
+                <div class="flex flex-wrap justify-center gap-2 px-4 pt-2">
+                    { for props.server.links.iter().map(|link| html! {
+                        <a href={link.url.clone()} target="_blank" rel="noopener noreferrer" class="text-sm font-medium text-blue-600 hover:underline dark:text-blue-500">{link.name.clone()}</a>
+                    })}
+                </div>
+                if props.server.status == Status::Unknown {
+                    <p class="px-4 pt-2 text-sm text-center text-red-600 dark:text-red-400">
+                        {props.server.reason.clone().unwrap_or_else(|| String::from("status is unknown"))}
+                    </p>
+                }
                 <div class="flex my-4 md:mt-6">
-                    <ServerDialog
-                        server_name={props.server.name.clone()}
-                        is_running={props.server.running}
-                        is_open={is_open.clone()}
-                    />
+                    if can_switch {
+                        <ServerDialog
+                            server_name={props.server.name.clone()}
+                            is_running={props.server.running}
+                            is_open={is_open.clone()}
+                        />
+                    }
                 </div>
             </div>
         </div>
@@ -88,8 +129,7 @@ pub fn ServerModal(props: &ServerDialogProps) -> Html {
         let is_open = props.is_open.clone();
         Callback::from(move |_: MouseEvent| {
             let server_name = server_name.clone();
-            let mut c = Configuration::new();
-            c.base_path = window().origin();
+            let c = configuration();
             spawn_local(async move {
                 match is_running {
                     true => {
@@ -0,0 +1,229 @@
+use gloo::utils::window;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+
+use openapi::apis::tokens_api::{create_api_token, delete_api_token, list_api_tokens};
+use openapi::models::{ApiToken, NewApiToken};
+
+use crate::state::configuration;
+
+// Choices of the expiry, in days
+const EXPIRES_IN_DAYS: [i64; 3] = [7, 14, 30];
+
+#[derive(PartialEq, Properties)]
+pub struct TokensProps {
+    pub servers: Vec<crate::state::Server>,
+}
+
+#[function_component]
+pub fn Tokens(props: &TokensProps) -> Html {
+    let tokens = use_state(|| vec![] as Vec<ApiToken>);
+    // incremented to reload tokens after changes
+    let generation = use_state(|| 0u32);
+    let created_token = use_state(|| None as Option<String>);
+    let error = use_state(|| None as Option<String>);
+
+    let name = use_state(String::new);
+    let read_only = use_state(|| false);
+    let machines = use_state(|| vec![] as Vec<String>);
+    let expires_in_days = use_state(|| EXPIRES_IN_DAYS[1]);
+
+    {
+        let tokens = tokens.clone();
+        let error = error.clone();
+        use_effect_with(*generation, move |_| {
+            spawn_local(async move {
+                match list_api_tokens(&configuration()).await {
+                    Ok(res) => tokens.set(res),
+                    Err(e) => error.set(Some(error_message(e))),
+                }
+            });
+        });
+    }
+
+    let on_create = {
+        let generation = generation.clone();
+        let created_token = created_token.clone();
+        let error = error.clone();
+        let name = name.clone();
+        let read_only = read_only.clone();
+        let machines = machines.clone();
+        let expires_in_days = expires_in_days.clone();
+        Callback::from(move |e: SubmitEvent| {
+            e.prevent_default();
+            let mut new_token = NewApiToken::new((*name).clone(), *expires_in_days * 24 * 60 * 60);
+            new_token.read_only = Some(*read_only);
+            // no machines selected means all machines
+            new_token.machines = Some((*machines).clone()).filter(|m| !m.is_empty());
+            let generation = generation.clone();
+            let created_token = created_token.clone();
+            let error = error.clone();
+            let name = name.clone();
+            spawn_local(async move {
+                match create_api_token(&configuration(), new_token).await {
+                    Ok(res) => {
+                        created_token.set(Some(res.token));
+                        error.set(None);
+                        name.set(String::new());
+                        generation.set(*generation + 1);
+                    }
+                    Err(e) => error.set(Some(error_message(e))),
+                }
+            });
+        })
+    };
+    let on_name_input = {
+        let name = name.clone();
+        Callback::from(move |e: InputEvent| {
+            name.set(e.target_unchecked_into::<HtmlInputElement>().value())
+        })
+    };
+    let on_read_only_change = {
+        let read_only = read_only.clone();
+        Callback::from(move |e: Event| {
+            read_only.set(e.target_unchecked_into::<HtmlInputElement>().checked())
+        })
+    };
+    let on_expires_change = {
+        let expires_in_days = expires_in_days.clone();
+        Callback::from(move |e: Event| {
+            let value = e.target_unchecked_into::<HtmlSelectElement>().value();
+            expires_in_days.set(value.parse().unwrap_or(EXPIRES_IN_DAYS[1]));
+        })
+    };
+
+    html! {
+        <div class="w-full max-w-4xl px-4">
+            <h3 class="text-xl font-medium text-gray-900 dark:text-white mb-4">{"API tokens"}</h3>
+            if let Some(message) = (*error).clone() {
+                <p class="mb-4 text-sm text-red-600">{message}</p>
+            }
+            if let Some(token) = (*created_token).clone() {
+                <div class="mb-4 p-4 text-sm bg-green-50 border border-green-200 rounded-lg">
+                    <p class="mb-2">{"Copy the new token now. It cannot be shown again."}</p>
+                    <code class="break-all select-all">{token}</code>
+                </div>
+            }
+
+            <form class="mb-6 p-4 bg-white border border-gray-200 rounded-lg shadow-sm" onsubmit={on_create}>
+                <div class="flex flex-wrap items-end gap-4">
+                    <label class="text-sm">
+                        {"Name"}
+                        <input class="block mt-1 border border-gray-300 rounded px-2 py-1" type="text" required=true
+                            value={(*name).clone()} oninput={on_name_input} />
+                    </label>
+                    <label class="text-sm">
+                        {"Expires in"}
+                        <select class="block mt-1 border border-gray-300 rounded px-2 py-1" onchange={on_expires_change}>
+                            { for EXPIRES_IN_DAYS.iter().map(|days| html! {
+                                <option value={days.to_string()} selected={*days == *expires_in_days}>{format!("{} days", days)}</option>
+                            })}
+                        </select>
+                    </label>
+                    <label class="text-sm flex items-center gap-1">
+                        <input type="checkbox" checked={*read_only} onchange={on_read_only_change} />
+                        {"Read-only"}
+                    </label>
+                </div>
+                <div class="flex flex-wrap gap-3 mt-3 text-sm">
+                    <span class="text-gray-500">{"Machines (all if none selected):"}</span>
+                    { for props.servers.iter().map(|server| {
+                        let server_name = server.name.clone();
+                        let checked = machines.contains(&server_name);
+                        let on_change = {
+                            let machines = machines.clone();
+                            let server_name = server_name.clone();
+                            Callback::from(move |_: Event| {
+                                let mut next: Vec<String> =
+                                    machines.iter().filter(|m| **m != server_name).cloned().collect();
+                                if !checked {
+                                    next.push(server_name.clone());
+                                }
+                                machines.set(next);
+                            })
+                        };
+                        html! {
+                            <label class="flex items-center gap-1">
+                                <input type="checkbox" checked={checked} onchange={on_change} />
+                                {server_name}
+                            </label>
+                        }
+                    })}
+                </div>
+                <button class="mt-4 text-white bg-blue-700 hover:bg-blue-800 font-medium rounded-lg text-sm px-5 py-2.5" type="submit">
+                    {"Create token"}
+                </button>
+            </form>
+
+            <table class="w-full text-sm text-left text-gray-700 bg-white border border-gray-200">
+                <thead class="bg-gray-50">
+                    <tr>
+                        <th class="px-3 py-2">{"Name"}</th>
+                        <th class="px-3 py-2">{"Scope"}</th>
+                        <th class="px-3 py-2">{"Expires at"}</th>
+                        <th class="px-3 py-2">{"Last used at"}</th>
+                        <th class="px-3 py-2"></th>
+                    </tr>
+                </thead>
+                <tbody>
+                    { for tokens.iter().map(|token| {
+                        let on_revoke = {
+                            let generation = generation.clone();
+                            let error = error.clone();
+                            let id = token.id;
+                            Callback::from(move |_: MouseEvent| {
+                                if !window().confirm_with_message("Revoke this token?").unwrap_or(false) {
+                                    return;
+                                }
+                                let generation = generation.clone();
+                                let error = error.clone();
+                                spawn_local(async move {
+                                    match delete_api_token(&configuration(), id).await {
+                                        Ok(()) => generation.set(*generation + 1),
+                                        Err(e) => error.set(Some(error_message(e))),
+                                    }
+                                });
+                            })
+                        };
+                        html! {
+                            <tr class="border-t border-gray-200">
+                                <td class="px-3 py-2">{token.name.clone()}</td>
+                                <td class="px-3 py-2">{scope_of(token)}</td>
+                                <td class="px-3 py-2">{token.expires_at.clone()}</td>
+                                <td class="px-3 py-2">{token.last_used_at.clone().unwrap_or_else(|| "-".to_string())}</td>
+                                <td class="px-3 py-2">
+                                    <button class="text-red-600 hover:underline" onclick={on_revoke}>{"Revoke"}</button>
+                                </td>
+                            </tr>
+                        }
+                    })}
+                </tbody>
+            </table>
+        </div>
+    }
+}
+
+fn scope_of(token: &ApiToken) -> String {
+    let access = if token.read_only {
+        "read-only"
+    } else {
+        "read-write"
+    };
+    match &token.machines {
+        Some(machines) => format!("{}: {}", access, machines.join(", ")),
+        None => format!("{}: all machines", access),
+    }
+}
+
+// Use the message of the error response body if exists.
+fn error_message<T>(e: openapi::apis::Error<T>) -> String {
+    match e {
+        openapi::apis::Error::ResponseError(res) => {
+            serde_json::from_str::<openapi::models::ErrorMessage>(&res.content)
+                .map(|m| m.error)
+                .unwrap_or(res.content)
+        }
+        e => e.to_string(),
+    }
+}
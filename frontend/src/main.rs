@@ -1,58 +1,53 @@
+use gloo::events::EventListener;
 use gloo::utils::window;
 use gloo_timers::callback::Interval;
-use js_sys::Date;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
 
-use machine_launcher_utils::{all_claims_from_jwt, COOKIE_KEY};
-use openapi::apis::app_api::list_servers;
-use openapi::apis::configuration::Configuration;
+use openapi::apis::app_api::{get_me, list_servers};
 
 mod components;
 use components::contents::Contents;
 use components::footer::Footer;
 use components::header::Header;
+use components::tokens::Tokens;
 
 mod state;
-use state::{Server, Userinfo};
+use state::{configuration, set_csrf_token, Page, Server, Userinfo};
 
 #[function_component]
 fn App() -> Html {
     // States
     let user = use_state(|| None as Option<Userinfo>);
     let servers = use_state(|| vec![] as Vec<Server>);
+    let page = use_state(Page::current);
 
     // Effects
+    {
+        let page = page.clone();
+        use_effect_with((), move |_| {
+            let listener =
+                EventListener::new(&window(), "hashchange", move |_| page.set(Page::current()));
+            move || drop(listener)
+        });
+    };
     {
         let user = user.clone();
-        use_effect(move || match wasm_cookies::all().unwrap().get(COOKIE_KEY) {
-            Some(token) => {
-                if user.is_none() {
-                    if let Ok(claims) = all_claims_from_jwt(token) {
-                        let expired_at = claims.get("exp").unwrap().as_f64().unwrap();
-                        let now = get_unix_time();
-                        if now > expired_at {
-                            // TODO: display error message in browser
-                            wasm_cookies::delete(COOKIE_KEY);
-                            user.set(None);
-                        } else {
-                            user.set(Some(Userinfo {
-                                name: claims
-                                    .get("name")
-                                    .map_or_else(|| "".to_string(), |v| v.to_string()),
-                                icon_url: claims
-                                    .get("picture")
-                                    .map_or_else(|| "".to_string(), |v| v.to_string()),
-                            }))
-                        }
+        use_effect_with((), move |_| {
+            let c = configuration();
+            spawn_local(async move {
+                // MEMO: the session cookie is HttpOnly, so ask the backend who is logged in
+                match get_me(&c).await {
+                    Ok(me) => {
+                        set_csrf_token(me.csrf_token);
+                        user.set(Some(Userinfo {
+                            name: me.name.unwrap_or_default(),
+                            icon_url: me.picture.unwrap_or_default(),
+                        }))
                     }
+                    Err(e) => gloo::console::log!(format!("{:?}", e)),
                 }
-            }
-            None => {
-                if user.is_some() {
-                    user.set(None)
-                }
-            }
+            });
         });
     };
     {
@@ -65,8 +60,7 @@ fn App() -> Html {
                 }
                 let servers = servers.clone();
                 let user = user.clone();
-                let mut c = Configuration::new();
-                c.base_path = window().origin();
+                let c = configuration();
                 spawn_local(async move {
                     match list_servers(&c).await {
                         Ok(res) => {
@@ -93,10 +87,14 @@ fn App() -> Html {
             <section class="machine-launcher">
                 <Header user={(*user).clone()} />
                 <div class="fixed w-screen flex justify-center ">
-                    <Contents
-                        user={(*user).clone()}
-                        servers={(*servers).clone()}
-                    />
+                    if *page == Page::Tokens && user.is_some() {
+                        <Tokens servers={(*servers).clone()} />
+                    } else {
+                        <Contents
+                            user={(*user).clone()}
+                            servers={(*servers).clone()}
+                        />
+                    }
                 </div>
                 <Footer />
             </section>
@@ -110,10 +108,6 @@ fn compare_servers(mut a: Vec<Server>, mut b: Vec<Server>) -> bool {
     a == b
 }
 
-fn get_unix_time() -> f64 {
-    Date::now() / 1000.0
-}
-
 fn main() {
     yew::Renderer::<App>::new().render();
 }
@@ -5,3 +5,78 @@ pub struct Userinfo {
 }
 
 pub type Server = openapi::models::Server;
+
+thread_local! {
+    // CSRF token of the session, got from `/api/me`
+    static CSRF_TOKEN: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+pub fn set_csrf_token(token: Option<String>) {
+    CSRF_TOKEN.with(|t| *t.borrow_mut() = token);
+}
+
+pub fn csrf_token() -> Option<String> {
+    CSRF_TOKEN.with(|t| t.borrow().clone())
+}
+
+/// Configuration of the API client, which sends the CSRF token required on changes.
+pub fn configuration() -> openapi::apis::configuration::Configuration {
+    let mut c = openapi::apis::configuration::Configuration::new();
+    c.base_path = gloo::utils::window().origin();
+    c.api_key = csrf_token().map(|key| openapi::apis::configuration::ApiKey { prefix: None, key });
+    c
+}
+
+// Pages switched by the URL fragment, e.g. `/#/tokens`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Page {
+    Servers,
+    Tokens,
+}
+impl Page {
+    pub fn current() -> Self {
+        match gloo::utils::window().location().hash().as_deref() {
+            Ok("#/tokens") => Page::Tokens,
+            _ => Page::Servers,
+        }
+    }
+}
+
+/// Path to return to after login: `return_to` passed to the top page by `/auth/login`,
+/// otherwise the current page, which is the top page with the fragment of `Page`.
+pub fn return_to() -> String {
+    let location = gloo::utils::window().location();
+    web_sys::UrlSearchParams::new_with_str(&location.search().unwrap_or_default())
+        .ok()
+        .and_then(|params| params.get("return_to"))
+        .unwrap_or_else(|| format!("/{}", location.hash().unwrap_or_default()))
+}
+
+/// Message of the failed local login, passed to the top page as `login_error` by the backend.
+pub fn login_error() -> Option<&'static str> {
+    let search = gloo::utils::window()
+        .location()
+        .search()
+        .unwrap_or_default();
+    let error = web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get("login_error")?;
+    Some(match error.as_str() {
+        "invalid_credentials" => "Username or password is wrong.",
+        "too_many_attempts" => "Too many login attempts. Please try again later.",
+        "disabled" => "Local accounts are not available now.",
+        _ => "Login failed.",
+    })
+}
+
+/// URL of `login_path` (e.g. `/auth/login`) which returns to `return_to()` after login.
+pub fn login_url(login_path: &str) -> String {
+    let return_to = return_to();
+    match web_sys::UrlSearchParams::new() {
+        Ok(params) if return_to != "/" => {
+            params.append("return_to", &return_to);
+            format!("{}?{}", login_path, String::from(params.to_string()))
+        }
+        _ => login_path.to_string(),
+    }
+}
@@ -4,19 +4,23 @@ use std::io::{Error, ErrorKind};
 use base64::prelude::*;
 use serde_json::Value;
 
-pub const COOKIE_KEY: &str = "token";
+// Cookie holding the session token
+pub const COOKIE_KEY: &str = "session";
 
 pub fn all_claims_from_jwt(jwt: &str) -> Result<HashMap<String, Value>, Error> {
-    let token_payload = String::from_utf8(
-        BASE64_STANDARD_NO_PAD
-            .decode(jwt.split('.').collect::<Vec<&str>>()[1])
-            .map_err(|e| {
-                Error::new(
-                    ErrorKind::InvalidInput,
-                    format!("failed to decode JWT from base64: {}", e),
-                )
-            })?,
-    )
+    let payload = jwt.split('.').nth(1).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Provided token is not JWT: no payload".to_string(),
+        )
+    })?;
+    // MEMO: segments of JWT are base64url without padding. (RFC 7515)
+    let token_payload = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(payload).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("failed to decode JWT from base64url: {}", e),
+        )
+    })?)
     .map_err(|e| {
         Error::new(
             ErrorKind::InvalidInput,
@@ -37,3 +41,73 @@ pub fn all_claims_from_jwt(jwt: &str) -> Result<HashMap<String, Value>, Error> {
     };
     Ok(all_claims)
 }
+
+// A row of the machine table printed by `ctl status` and the CLI
+pub struct MachineRow<'a> {
+    pub name: &'a str,
+    pub hostname: &'a str,
+    // `running`, `stopped` or `unknown`
+    pub status: &'a str,
+    pub reason: Option<&'a str>,
+}
+
+// Format machines as a plain text table whose columns are aligned.
+pub fn format_machine_table<'a>(machines: impl IntoIterator<Item = MachineRow<'a>>) -> String {
+    let header = ["NAME", "HOSTNAME", "STATUS", "REASON"].map(String::from);
+    let rows: Vec<[String; 4]> = machines
+        .into_iter()
+        .map(|m| {
+            [
+                m.name.to_string(),
+                m.hostname.to_string(),
+                m.status.to_string(),
+                m.reason.unwrap_or_default().to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|cells| {
+            let line: Vec<String> = cells
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            format!("{}\n", line.join("  ").trim_end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_claims_from_jwt_decodes_base64url_payload() {
+        // `{"sub":"a>b?","name":"~~~"}` contains `-` and `_` in base64url
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(r#"{"sub":"a>b?","name":"~~~"}"#);
+        assert!(payload.contains('-') && payload.contains('_'));
+        let claims = all_claims_from_jwt(&format!("header.{}.signature", payload)).unwrap();
+        assert_eq!(claims["sub"], "a>b?");
+        assert_eq!(claims["name"], "~~~");
+    }
+
+    #[test]
+    fn all_claims_from_jwt_rejects_malformed_tokens() {
+        assert!(all_claims_from_jwt("no-dots").is_err());
+        assert!(all_claims_from_jwt("header.!!!.signature").is_err());
+        let payload = BASE64_URL_SAFE_NO_PAD.encode("[]");
+        assert!(
+            all_claims_from_jwt(&format!("header.{}.signature", payload))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}